@@ -0,0 +1,287 @@
+use crate::shell;
+use anyhow::{anyhow, bail, Context, Result};
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    imageops::FilterType,
+    Delay, Frame,
+};
+use ipfs_api::{IpfsApi, IpfsClient};
+use std::{
+    fmt,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    process::Stdio,
+    str::FromStr,
+    time::SystemTime,
+};
+use tokio::{io::AsyncWriteExt, process::Child};
+use tokio_stream::StreamExt;
+use tracing::{debug, info, warn};
+
+pub const ANIMATIONS_FOLDER_NAME: &str = "animations";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnimationFormat {
+    Gif,
+    Webp,
+    Mp4,
+}
+
+impl AnimationFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Webp => "webp",
+            AnimationFormat::Mp4 => "mp4",
+        }
+    }
+}
+
+impl FromStr for AnimationFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "gif" => Ok(AnimationFormat::Gif),
+            "webp" => Ok(AnimationFormat::Webp),
+            "mp4" => Ok(AnimationFormat::Mp4),
+            _ => bail!(
+                "Unknown animation format \"{}\", expected one of \"gif\", \"webp\" or \"mp4\"",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for AnimationFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationSettings {
+    pub format: AnimationFormat,
+    pub fps: u32,
+    /// Command which loops the animation as wallpaper, `{path}` is replaced with its path.
+    pub command: Option<String>,
+}
+
+/// Downloads the evolution frames to `frames_folder_path` in order.
+///
+/// `frames` are the ipfs paths of the evolutions, already sorted.
+pub async fn download_frames(
+    client: &IpfsClient,
+    frames: &[String],
+    frames_folder_path: &Path,
+) -> Result<Vec<PathBuf>> {
+    tokio::fs::create_dir_all(frames_folder_path).await?;
+    let mut paths = Vec::with_capacity(frames.len());
+    for (index, frame) in frames.iter().enumerate() {
+        // Sequential names so that ffmpeg can read them as an image sequence.
+        let path = frames_folder_path.join(format!("{:05}.jpg", index + 1));
+        let mut file = tokio::fs::File::create(&path).await?;
+        let mut stream = client.cat(frame);
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|err| anyhow!("Couldn't download \"{}\": {}", frame, err))?;
+            file.write_all(&chunk).await?;
+        }
+        file.shutdown().await?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Assembles the frames into a looping animation.
+///
+/// Gif is encoded in process, webp and mp4 need `ffmpeg` in `PATH`.
+pub async fn encode(
+    frames: Vec<PathBuf>,
+    format: AnimationFormat,
+    fps: u32,
+    output_path: &Path,
+) -> Result<()> {
+    if frames.is_empty() {
+        bail!("No frames to animate");
+    }
+    match format {
+        AnimationFormat::Gif => {
+            let output_path = output_path.to_owned();
+            tokio::task::spawn_blocking(move || encode_gif(&frames, fps, &output_path)).await?
+        }
+        AnimationFormat::Webp | AnimationFormat::Mp4 => {
+            // Unwrap is safe here because there is at least one frame.
+            let frames_folder_path = frames[0].parent().unwrap();
+            encode_with_ffmpeg(frames_folder_path, format, fps, output_path).await
+        }
+    }
+}
+
+fn encode_gif(frames: &[PathBuf], fps: u32, output_path: &Path) -> Result<()> {
+    let file = BufWriter::new(File::create(output_path)?);
+    let mut encoder = GifEncoder::new_with_speed(file, 10);
+    encoder.set_repeat(Repeat::Infinite)?;
+
+    let delay = Delay::from_numer_denom_ms(1000, fps.max(1));
+    let mut size = None;
+    for path in frames {
+        // Frames may be of any still image format even though they are named `.jpg`.
+        let mut image = image::ImageReader::open(path)?
+            .with_guessed_format()?
+            .decode()
+            .with_context(|| format!("Couldn't read frame \"{}\"", path.display()))?
+            .to_rgba8();
+        // Gif frames have to be of the same size, scale the odd ones to the first.
+        let (width, height) = *size.get_or_insert(image.dimensions());
+        if image.dimensions() != (width, height) {
+            image = image::imageops::resize(&image, width, height, FilterType::Triangle);
+        }
+        encoder.encode_frame(Frame::from_parts(image, 0, 0, delay))?;
+    }
+    Ok(())
+}
+
+async fn encode_with_ffmpeg(
+    frames_folder_path: &Path,
+    format: AnimationFormat,
+    fps: u32,
+    output_path: &Path,
+) -> Result<()> {
+    let mut command = tokio::process::Command::new("ffmpeg");
+    command
+        .args(["-y", "-loglevel", "error", "-framerate"])
+        .arg(fps.to_string())
+        .arg("-i")
+        .arg(frames_folder_path.join("%05d.jpg"));
+    match format {
+        AnimationFormat::Mp4 => command.args(["-c:v", "libx264", "-pix_fmt", "yuv420p"]),
+        AnimationFormat::Webp => command.args(["-c:v", "libwebp", "-loop", "0"]),
+        AnimationFormat::Gif => unreachable!(),
+    };
+    let output = command
+        .arg(output_path)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .context("Couldn't run \"ffmpeg\", is it installed?")?;
+    if !output.status.success() {
+        bail!(
+            "\"ffmpeg\" failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Keeps the process which loops the animated wallpaper, only one runs at a time.
+#[derive(Debug, Default)]
+pub struct AnimatedWallpaper {
+    player: Option<Child>,
+}
+
+impl AnimatedWallpaper {
+    /// Starts looping the animation with the command, stopping the previous one.
+    pub fn show(&mut self, command: &str, path: &Path) -> Result<()> {
+        self.stop();
+
+        let command = command.replace("{path}", &shell::quote(&path.to_string_lossy()));
+        debug!(%command, "starting animated wallpaper");
+        let mut player = shell::command(&command);
+        // In a group of its own so that stopping it stops whatever the shell started too.
+        #[cfg(unix)]
+        player.process_group(0);
+        let player = player
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            // Don't leave the player behind when pollenwall exits.
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Couldn't run \"{}\"", command))?;
+        self.player = Some(player);
+        info!(path = %path.display(), "animated wallpaper set");
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(mut player) = self.player.take() {
+            #[cfg(unix)]
+            if let Some(pid) = player.id() {
+                // Safe, it only sends a signal to the group which the player leads.
+                if unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGTERM) } != 0 {
+                    warn!(error = %std::io::Error::last_os_error(), "failed to stop animated wallpaper");
+                }
+            }
+            // The player exits on its own once its group is stopped, otherwise it is killed.
+            let _ = player.start_kill();
+        }
+    }
+}
+
+impl Drop for AnimatedWallpaper {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Removes the animations in the folder which are older than `keep`.
+///
+/// Newer ones are being encoded or shown by other tasks, frame folders are left to the tasks which are still animating them.
+pub async fn clear_previous_animations(animations_folder_path: &Path, keep: &Path) {
+    let kept_at = match modified(keep).await {
+        Some(kept_at) => kept_at,
+        None => return,
+    };
+    if let Ok(mut directory_reader) = tokio::fs::read_dir(animations_folder_path).await {
+        while let Ok(Some(entry)) = directory_reader.next_entry().await {
+            let path = entry.path();
+            if path == keep || !path.is_file() {
+                continue;
+            }
+            if modified(&path)
+                .await
+                .is_none_or(|modified_at| modified_at >= kept_at)
+            {
+                continue;
+            }
+            if let Err(err) = tokio::fs::remove_file(&path).await {
+                warn!(path = %path.display(), error = %err, "failed to remove previous animation");
+            }
+        }
+    }
+}
+
+async fn modified(path: &Path) -> Option<SystemTime> {
+    tokio::fs::metadata(path).await.ok()?.modified().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::TempFolder;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn clears_only_older_animations() {
+        let folder_path = TempFolder::new("animations");
+        let now = SystemTime::now();
+        let path = |name: &str, age: u64| {
+            let path = folder_path.join(name);
+            File::create(&path)
+                .unwrap()
+                .set_modified(now - Duration::from_secs(age))
+                .unwrap();
+            path
+        };
+        let older = path("older.mp4", 20);
+        let keep = path("keep.mp4", 10);
+        // Written by another task after this one finished.
+        let newer = path("newer.mp4", 0);
+
+        clear_previous_animations(&folder_path, &keep).await;
+        assert!(!older.exists());
+        assert!(keep.exists());
+        assert!(newer.exists());
+    }
+}
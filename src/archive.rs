@@ -0,0 +1,134 @@
+use crate::{files, status::unix_secs};
+use anyhow::{bail, Result};
+use ipfs_api::{IpfsApi, IpfsClient};
+use serde::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use tokio::io::AsyncWriteExt;
+use tokio_stream::StreamExt;
+use tracing::debug;
+
+pub const ARCHIVE_FOLDER_NAME: &str = "archive";
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Describes an archived pollen, stored next to its files as `manifest.json`.
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub pollen_uuid: String,
+    /// Ipfs hash of the done pollen which was mirrored.
+    pub cid: String,
+    pub model: Option<String>,
+    pub prompt: Option<String>,
+    /// Seconds since unix epoch.
+    pub archived_at: u64,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestFile {
+    /// Path relative to the pollen folder, for example `input/text_input`.
+    pub path: PathBuf,
+    pub cid: String,
+    /// Size as reported by ipfs.
+    pub size: u64,
+}
+
+pub fn pollen_folder_path(app_folder_path: &Path, pollen_uuid: &str) -> PathBuf {
+    app_folder_path.join(ARCHIVE_FOLDER_NAME).join(pollen_uuid)
+}
+
+/// Mirrors every file of the pollen (inputs, outputs, logs, videos) into
+/// `~/.pollenwall/archive/<pollen-uuid>` and writes its manifest.
+///
+/// Returns `None` if the pollen was archived before.
+pub async fn archive_pollen(
+    client: &IpfsClient,
+    app_folder_path: &Path,
+    pollen_uuid: &str,
+    cid: &str,
+    model: Option<String>,
+    prompt: Option<String>,
+) -> Result<Option<Manifest>> {
+    let pollen_folder_path = pollen_folder_path(app_folder_path, pollen_uuid);
+    let manifest_path = pollen_folder_path.join(MANIFEST_FILE_NAME);
+    // The manifest is written last so its presence means the archive is complete.
+    if manifest_path.exists() {
+        return Ok(None);
+    }
+
+    let mut files = Vec::new();
+    // Directories left to walk, as (relative path, ipfs path).
+    let mut directories = vec![(PathBuf::new(), format!("/ipfs/{}", cid))];
+    while let Some((relative_path, ipfs_path)) = directories.pop() {
+        let listing = client.file_ls(&ipfs_path).await?;
+        for object in listing.objects.values() {
+            for link in &object.links {
+                if !is_safe_name(&link.name) {
+                    debug!(pollen = %pollen_uuid, name = %link.name, "skipped unsafe file name");
+                    continue;
+                }
+                let link_relative_path = relative_path.join(&link.name);
+                let link_ipfs_path = format!("{}/{}", ipfs_path, link.name);
+                if link.typ.as_deref() == Some("Directory") {
+                    directories.push((link_relative_path, link_ipfs_path));
+                } else {
+                    let path = pollen_folder_path.join(&link_relative_path);
+                    download(client, &link_ipfs_path, &path).await?;
+                    files.push(ManifestFile {
+                        path: link_relative_path,
+                        cid: link.hash.clone(),
+                        size: link.size,
+                    });
+                }
+            }
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let manifest = Manifest {
+        pollen_uuid: pollen_uuid.to_owned(),
+        cid: cid.to_owned(),
+        model,
+        prompt,
+        archived_at: unix_secs(SystemTime::now()),
+        files,
+    };
+    tokio::fs::create_dir_all(&pollen_folder_path).await?;
+    files::write_atomic(&manifest_path, serde_json::to_vec_pretty(&manifest)?)?;
+    Ok(Some(manifest))
+}
+
+// Names come from the network so make sure they stay inside the pollen folder.
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\'])
+}
+
+async fn download(client: &IpfsClient, ipfs_path: &str, path: &Path) -> Result<()> {
+    // Files of an interrupted archive are kept, their content doesn't change.
+    if path.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    // Write to a temporary file first so a failed download never looks complete.
+    let tmp_path = files::temp_path(path);
+    let mut file = tokio::fs::File::create(&tmp_path).await?;
+    let mut stream = client.cat(ipfs_path);
+    while let Some(chunk) = stream.next().await {
+        match chunk {
+            Ok(chunk) => file.write_all(&chunk).await?,
+            Err(err) => {
+                drop(file);
+                let _ = tokio::fs::remove_file(&tmp_path).await;
+                bail!("Couldn't download \"{}\": {}", ipfs_path, err);
+            }
+        }
+    }
+    file.shutdown().await?;
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
@@ -0,0 +1,256 @@
+use crate::{files, status::Status, Model, PollenInfo};
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    fmt,
+    io::{self, BufRead, Write},
+    path::Path,
+    str::FromStr,
+};
+
+const ATTACH_REQUEST_FILE_NAME: &str = "attach_request";
+
+/// Which processing pollen attach mode follows.
+///
+/// Written as `first`, `latest`, `uuid:<uuid>`, `prompt:<keyword>` or `model:<model>`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum AttachSelector {
+    /// The processing pollen which was seen first.
+    #[default]
+    First,
+    /// The processing pollen which was seen last.
+    MostRecent,
+    /// The pollen with this uuid, a prefix of it is enough.
+    Uuid(String),
+    /// A pollen whose prompt contains the keyword, case insensitive.
+    Prompt(String),
+    Model(Model),
+}
+
+impl FromStr for AttachSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "first" => return Ok(AttachSelector::First),
+            "latest" | "most-recent" => return Ok(AttachSelector::MostRecent),
+            _ => {}
+        }
+        match s.split_once(':') {
+            Some(("uuid", uuid)) if !uuid.is_empty() => Ok(AttachSelector::Uuid(uuid.into())),
+            Some(("prompt", keyword)) if !keyword.is_empty() => {
+                Ok(AttachSelector::Prompt(keyword.to_lowercase()))
+            }
+            Some(("model", model)) => Ok(AttachSelector::Model(model.parse()?)),
+            _ => bail!(
+                "Invalid attach selector \"{}\", expected one of \"first\", \"latest\", \"uuid:<uuid>\", \"prompt:<keyword>\" or \"model:<model>\"",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for AttachSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttachSelector::First => write!(f, "first"),
+            AttachSelector::MostRecent => write!(f, "latest"),
+            AttachSelector::Uuid(uuid) => write!(f, "uuid:{}", uuid),
+            AttachSelector::Prompt(keyword) => write!(f, "prompt:{}", keyword),
+            AttachSelector::Model(model) => write!(f, "model:{}", model.name()),
+        }
+    }
+}
+
+impl AttachSelector {
+    pub fn matches(&self, pollen_uuid: &str, pollen: &PollenInfo) -> bool {
+        match self {
+            AttachSelector::First | AttachSelector::MostRecent => true,
+            AttachSelector::Uuid(uuid) => pollen_uuid.starts_with(uuid.as_str()),
+            AttachSelector::Prompt(keyword) => pollen
+                .prompt()
+                .is_some_and(|prompt| prompt.to_lowercase().contains(keyword)),
+            AttachSelector::Model(model) => pollen.model_type.as_ref() == Some(model),
+        }
+    }
+
+    /// Picks the pollen to attach among the candidates, `None` if none of them matches.
+    pub fn select<'a>(
+        &self,
+        candidates: impl Iterator<Item = (&'a String, &'a PollenInfo)>,
+    ) -> Option<&'a String> {
+        let matching = candidates.filter(|(uuid, pollen)| self.matches(uuid, pollen));
+        let selected = match self {
+            AttachSelector::MostRecent => matching.max_by_key(|(_, pollen)| pollen.first_seen),
+            _ => matching.min_by_key(|(_, pollen)| pollen.first_seen),
+        };
+        selected.map(|(uuid, _)| uuid)
+    }
+}
+
+/// Asks the running `pollenwall` to attach with this selector, it picks the request up on its next status update.
+pub fn request(app_folder_path: &Path, selector: &AttachSelector) -> Result<()> {
+    let path = app_folder_path.join(ATTACH_REQUEST_FILE_NAME);
+    files::write_atomic(&path, selector.to_string())
+}
+
+/// Takes the pending attach request if there is one.
+pub fn take_request(app_folder_path: &Path) -> Result<Option<AttachSelector>> {
+    let path = app_folder_path.join(ATTACH_REQUEST_FILE_NAME);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(_) => return Ok(None),
+    };
+    std::fs::remove_file(&path)
+        .with_context(|| format!("Couldn't remove \"{}\"", path.display()))?;
+    contents
+        .trim()
+        .parse()
+        .map(Some)
+        .map_err(|err| anyhow!("Invalid attach request: {}", err))
+}
+
+/// Lists the processing pollens of the running `pollenwall` and lets the user pick one.
+pub fn choose(status: &Status) -> Result<Option<AttachSelector>> {
+    if status.processing.is_empty() {
+        println!("There are no processing pollens right now.");
+        return Ok(None);
+    }
+    for (index, pollen) in status.processing.iter().enumerate() {
+        let attached = status.attached_pollen.as_deref() == Some(pollen.uuid.as_str());
+        println!(
+            "{:>3}) {}{}  {}  {}",
+            index + 1,
+            pollen.uuid,
+            if attached { " (attached)" } else { "" },
+            pollen.model.as_deref().unwrap_or("-"),
+            pollen.prompt.as_deref().unwrap_or_default()
+        );
+    }
+    print!("Pollen to attach (empty to cancel): ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim();
+    if answer.is_empty() {
+        return Ok(None);
+    }
+    let pollen = answer
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_sub(1))
+        .and_then(|index| status.processing.get(index))
+        .ok_or_else(|| anyhow!("No pollen numbered \"{}\"", answer))?;
+    Ok(Some(AttachSelector::Uuid(pollen.uuid.clone())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::TempFolder;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn parses_what_it_displays() {
+        for selector in [
+            "first",
+            "latest",
+            "uuid:3f2a",
+            "prompt:lake",
+            "model:vit_b32",
+        ] {
+            assert_eq!(
+                selector.parse::<AttachSelector>().unwrap().to_string(),
+                selector
+            );
+        }
+        assert_eq!(
+            "most-recent".parse::<AttachSelector>().unwrap(),
+            AttachSelector::MostRecent
+        );
+        assert_eq!(
+            "prompt:Lake".parse::<AttachSelector>().unwrap(),
+            AttachSelector::Prompt("lake".to_owned())
+        );
+        for selector in ["", "last", "uuid:", "prompt:", "model:clip", "color:red"] {
+            assert!(selector.parse::<AttachSelector>().is_err(), "{}", selector);
+        }
+    }
+
+    #[test]
+    fn matches_pollens() {
+        let pollen = PollenInfo {
+            model_type: Some(Model::VitB32),
+            text_input: Some("\"A Lake at dawn\"".to_owned()),
+            ..PollenInfo::default()
+        };
+        let matches = |selector: &str| {
+            selector
+                .parse::<AttachSelector>()
+                .unwrap()
+                .matches("3f2a9c", &pollen)
+        };
+        assert!(matches("first"));
+        assert!(matches("uuid:3f2a"));
+        assert!(!matches("uuid:9c"));
+        assert!(matches("prompt:LAKE"));
+        assert!(!matches("prompt:river"));
+        assert!(matches("model:vit_b32"));
+        assert!(!matches("model:wiki_art"));
+        // Nor does a pollen without a prompt.
+        assert!(
+            !AttachSelector::Prompt("lake".to_owned()).matches("3f2a9c", &PollenInfo::default())
+        );
+    }
+
+    #[test]
+    fn selects_the_first_or_the_most_recent() {
+        let first_seen = Instant::now();
+        let pollens = [("older", 0), ("newest", 20), ("middle", 10)]
+            .into_iter()
+            .map(|(uuid, secs)| {
+                let pollen = PollenInfo {
+                    first_seen: first_seen + Duration::from_secs(secs),
+                    model_type: Some(if uuid == "newest" {
+                        Model::WikiArt
+                    } else {
+                        Model::VitB32
+                    }),
+                    ..PollenInfo::default()
+                };
+                (uuid.to_owned(), pollen)
+            })
+            .collect::<Vec<_>>();
+        let select = |selector: AttachSelector| {
+            selector
+                .select(pollens.iter().map(|(uuid, pollen)| (uuid, pollen)))
+                .map(String::as_str)
+        };
+        assert_eq!(select(AttachSelector::First), Some("older"));
+        assert_eq!(select(AttachSelector::MostRecent), Some("newest"));
+        assert_eq!(select(AttachSelector::Model(Model::VitB32)), Some("older"));
+        assert_eq!(
+            select(AttachSelector::Uuid("mid".to_owned())),
+            Some("middle")
+        );
+        assert_eq!(select(AttachSelector::Model(Model::GuidedDiffusion)), None);
+    }
+
+    #[test]
+    fn takes_requests_once() {
+        let folder = TempFolder::new("attach");
+        assert!(take_request(&folder).unwrap().is_none());
+
+        request(&folder, &AttachSelector::Prompt("lake".to_owned())).unwrap();
+        assert_eq!(
+            take_request(&folder).unwrap(),
+            Some(AttachSelector::Prompt("lake".to_owned()))
+        );
+        assert!(take_request(&folder).unwrap().is_none());
+
+        // An invalid request is taken too, so it doesn't come back on every update.
+        std::fs::write(folder.join(ATTACH_REQUEST_FILE_NAME), "color:red").unwrap();
+        assert!(take_request(&folder).is_err());
+        assert!(take_request(&folder).unwrap().is_none());
+    }
+}
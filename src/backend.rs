@@ -0,0 +1,200 @@
+use crate::shell;
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use tracing::{debug, info};
+
+/// Sets the wallpaper on the desktop.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Backend {
+    /// The platform's own way, sets the same wallpaper on every output.
+    System,
+    /// A command where `{path}` is replaced with the wallpaper path and `{output}` with the output name.
+    Command(String),
+    /// Only logs what the backend it stands in for would do.
+    DryRun(Box<Backend>),
+}
+
+impl Backend {
+    /// The backend for `--dry-run` and `--wallpaper-command`.
+    pub fn new(dry_run: bool, command: Option<&str>) -> Self {
+        let backend = match command {
+            Some(command) => Backend::Command(command.to_owned()),
+            None => Backend::System,
+        };
+        if dry_run {
+            Backend::DryRun(Box::new(backend))
+        } else {
+            backend
+        }
+    }
+
+    /// Whether a different wallpaper can be set on each output.
+    pub fn supports_outputs(&self) -> bool {
+        match self {
+            Backend::System => false,
+            Backend::Command(_) => true,
+            Backend::DryRun(backend) => backend.supports_outputs(),
+        }
+    }
+
+    /// The wallpaper which is set now, `None` if the backend can't tell.
+    pub fn get(&self) -> Result<Option<PathBuf>> {
+        match self {
+            Backend::System => wallpaper::get()
+                .map(|path| Some(PathBuf::from(path)).filter(|path| !path.as_os_str().is_empty()))
+                .map_err(|err| anyhow!("Couldn't get the wallpaper: {}", err)),
+            Backend::Command(_) | Backend::DryRun(_) => Ok(None),
+        }
+    }
+
+    /// What setting the wallpaper on an output runs, the command or the platform's own way.
+    pub fn plan(&self, output: Option<&str>, path: &Path) -> Result<Plan> {
+        match self {
+            Backend::System => {
+                if let Some(output) = output {
+                    bail!(
+                        "Can't set the wallpaper of output \"{}\" without a wallpaper command",
+                        output
+                    );
+                }
+                let path = path
+                    .to_str()
+                    .ok_or_else(|| anyhow!("Invalid wallpaper path \"{}\"", path.display()))?;
+                Ok(Plan::System(path.to_owned()))
+            }
+            Backend::Command(command) => Ok(Plan::Command(
+                command
+                    .replace("{path}", &shell::quote(&path.to_string_lossy()))
+                    .replace("{output}", &shell::quote(output.unwrap_or("*"))),
+            )),
+            Backend::DryRun(backend) => backend.plan(output, path),
+        }
+    }
+
+    /// Sets the wallpaper on an output, on every output when it is `None`.
+    pub async fn set(&self, output: Option<&str>, path: &Path) -> Result<()> {
+        let plan = self.plan(output, path)?;
+        if let Backend::DryRun(_) = self {
+            info!(
+                output = output.unwrap_or("*"),
+                path = %path.display(),
+                would = %plan,
+                "dry run, would set wallpaper"
+            );
+            return Ok(());
+        }
+        match plan {
+            Plan::System(path) => wallpaper::set_from_path(&path).map_err(|err| anyhow!("{}", err)),
+            Plan::Command(command) => {
+                debug!(%command, "setting wallpaper");
+                let result = shell::command(&command)
+                    .stdin(Stdio::null())
+                    .output()
+                    .await
+                    .with_context(|| format!("Couldn't run \"{}\"", command))?;
+                if !result.status.success() {
+                    bail!(
+                        "Wallpaper command failed: {}",
+                        String::from_utf8_lossy(&result.stderr).trim()
+                    );
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How a wallpaper is set.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Plan {
+    /// With the platform's own way, on every output.
+    System(String),
+    /// With a shell command.
+    Command(String),
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Plan::System(path) => write!(f, "system wallpaper {}", path),
+            Plan::Command(command) => f.write_str(command),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::TempFolder;
+
+    #[test]
+    fn selects_backend_from_options() {
+        assert_eq!(Backend::new(false, None), Backend::System);
+        assert_eq!(
+            Backend::new(false, Some("swww img {path}")),
+            Backend::Command("swww img {path}".to_owned())
+        );
+        assert_eq!(
+            Backend::new(true, None),
+            Backend::DryRun(Box::new(Backend::System))
+        );
+        assert_eq!(
+            Backend::new(true, Some("swww img {path}")),
+            Backend::DryRun(Box::new(Backend::Command("swww img {path}".to_owned())))
+        );
+    }
+
+    #[test]
+    fn plans_like_the_backend_it_stands_in_for() {
+        let path = Path::new("/tmp/pollen.jpg");
+        for backend in [Backend::new(false, None), Backend::new(true, None)] {
+            assert_eq!(
+                backend.plan(None, path).unwrap(),
+                Plan::System("/tmp/pollen.jpg".to_owned())
+            );
+            assert!(backend.plan(Some("DP-1"), path).is_err());
+        }
+        for backend in [
+            Backend::new(false, Some("swww img -o {output} {path}")),
+            Backend::new(true, Some("swww img -o {output} {path}")),
+        ] {
+            assert_eq!(
+                backend.plan(Some("DP-1"), path).unwrap(),
+                Plan::Command(format!(
+                    "swww img -o {} {}",
+                    shell::quote("DP-1"),
+                    shell::quote("/tmp/pollen.jpg")
+                ))
+            );
+            assert_eq!(
+                backend.plan(None, path).unwrap(),
+                Plan::Command(format!(
+                    "swww img -o {} {}",
+                    shell::quote("*"),
+                    shell::quote("/tmp/pollen.jpg")
+                ))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn dry_run_runs_nothing() {
+        let folder_path = TempFolder::new("dry-run");
+        let marker = folder_path.join("marker");
+        let command = format!("touch {}", shell::quote(&marker.to_string_lossy()));
+        Backend::new(true, Some(&command))
+            .set(Some("DP-1"), Path::new("/tmp/pollen.jpg"))
+            .await
+            .unwrap();
+        assert!(!marker.exists());
+        // Fails as setting it for real would.
+        assert!(Backend::new(true, None)
+            .set(Some("DP-1"), Path::new("/tmp/pollen.jpg"))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use crate::files;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+pub const CACHE_FOLDER_NAME: &str = "cache";
+const INDEX_FILE_NAME: &str = "index.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    file_name: String,
+    size: u64,
+    /// Milliseconds since unix epoch.
+    last_used: u64,
+}
+
+/// Downloaded evolutions keyed by their ipfs hash in `~/.pollenwall/cache`.
+///
+/// Files are named `<cid>.<extension>`, the least recently used ones are evicted
+/// once the cache grows over its size.
+#[derive(Debug)]
+pub struct Cache {
+    folder_path: PathBuf,
+    /// Bytes the cache grows to before evicting.
+    pub max_size: u64,
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl Cache {
+    /// Loads the index from the cache folder, entries whose file is gone are forgotten.
+    pub fn load(app_folder_path: &Path, max_size: u64) -> Self {
+        let folder_path = app_folder_path.join(CACHE_FOLDER_NAME);
+        let index_path = folder_path.join(INDEX_FILE_NAME);
+        let mut entries: HashMap<String, CacheEntry> = match std::fs::read(&index_path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|err| {
+                warn!(path = %index_path.display(), error = %err, "couldn't parse cache index, starting a new one");
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        entries.retain(|_, entry| folder_path.join(&entry.file_name).exists());
+        // Left over from downloads which were interrupted.
+        if let Ok(directory) = std::fs::read_dir(&folder_path) {
+            for entry in directory.flatten() {
+                if entry.file_name().to_string_lossy().ends_with(".part") {
+                    let _ = std::fs::remove_file(entry.path());
+                }
+            }
+        }
+        Self {
+            folder_path,
+            max_size,
+            entries,
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        std::fs::create_dir_all(&self.folder_path)?;
+        let path = self.folder_path.join(INDEX_FILE_NAME);
+        files::write_atomic(&path, serde_json::to_vec_pretty(&self.entries)?)
+    }
+
+    /// Where the evolution named `name` with the hash `cid` is cached.
+    pub fn path(&self, cid: &str, name: &str) -> PathBuf {
+        match Path::new(name).extension() {
+            Some(extension) => {
+                self.folder_path
+                    .join(format!("{}.{}", cid, extension.to_string_lossy()))
+            }
+            None => self.folder_path.join(cid),
+        }
+    }
+
+    /// Path of the cached evolution, marking it as used.
+    pub fn get(&mut self, cid: &str) -> Option<PathBuf> {
+        let entry = self.entries.get_mut(cid)?;
+        let path = self.folder_path.join(&entry.file_name);
+        if !path.exists() {
+            self.entries.remove(cid);
+            return None;
+        }
+        entry.last_used = now();
+        Some(path)
+    }
+
+    /// Adds the evolution which is downloaded to `path`.
+    pub fn insert(&mut self, cid: &str, path: &Path) {
+        let file_name = match path.file_name() {
+            Some(file_name) => file_name.to_string_lossy().into_owned(),
+            None => return,
+        };
+        let size = std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+        self.entries.insert(
+            cid.to_owned(),
+            CacheEntry {
+                file_name,
+                size,
+                last_used: now(),
+            },
+        );
+    }
+
+    /// Forgets the evolution and returns its file to remove.
+    pub fn remove(&mut self, cid: &str) -> Option<PathBuf> {
+        let entry = self.entries.remove(cid)?;
+        Some(self.folder_path.join(entry.file_name))
+    }
+
+    /// Whether the file is a cached evolution.
+    pub fn contains(&self, path: &Path) -> bool {
+        path.parent() == Some(self.folder_path.as_path())
+            && self
+                .entries
+                .values()
+                .any(|entry| path.file_name() == Some(entry.file_name.as_ref()))
+    }
+
+    /// Forgets the least recently used evolutions until the cache fits its size and returns their files to remove.
+    ///
+    /// Files which are `pinned` are kept even if the cache stays over its size.
+    pub fn evict(&mut self, pinned: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+        let mut size: u64 = self.entries.values().map(|entry| entry.size).sum();
+        let mut entries: Vec<(&String, &CacheEntry)> = self.entries.iter().collect();
+        entries.sort_by_key(|(_, entry)| entry.last_used);
+
+        let mut evicted = Vec::new();
+        for (cid, entry) in entries {
+            if size <= self.max_size {
+                break;
+            }
+            let path = self.folder_path.join(&entry.file_name);
+            if pinned(&path) {
+                continue;
+            }
+            size -= entry.size;
+            evicted.push((cid.clone(), path));
+        }
+        for (cid, _) in &evicted {
+            self.entries.remove(cid);
+        }
+        evicted.into_iter().map(|(_, path)| path).collect()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::TempFolder;
+
+    // A cache of `max_size` bytes with an evolution of `size` bytes for each `(cid, size, last_used)`.
+    fn cache(max_size: u64, evolutions: &[(&str, usize, u64)]) -> (TempFolder, Cache) {
+        let app_folder_path = TempFolder::new("cache");
+        std::fs::create_dir_all(app_folder_path.join(CACHE_FOLDER_NAME)).unwrap();
+        let mut cache = Cache::load(&app_folder_path, max_size);
+        for (cid, size, last_used) in evolutions {
+            let path = cache.path(cid, "evolution.jpg");
+            std::fs::write(&path, vec![0; *size]).unwrap();
+            cache.insert(cid, &path);
+            cache.entries.get_mut(*cid).unwrap().last_used = *last_used;
+        }
+        (app_folder_path, cache)
+    }
+
+    fn file_names(paths: &[PathBuf]) -> Vec<String> {
+        paths
+            .iter()
+            .filter_map(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn evicts_least_recently_used_first() {
+        let (_folder, mut cache) = cache(20, &[("a", 10, 3), ("b", 10, 1), ("c", 10, 2)]);
+        assert_eq!(file_names(&cache.evict(|_| false)), ["b.jpg"]);
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+    }
+
+    #[test]
+    fn evicts_until_within_size() {
+        let (_folder, mut cache) =
+            cache(15, &[("a", 10, 1), ("b", 10, 2), ("c", 10, 3), ("d", 5, 4)]);
+        assert_eq!(file_names(&cache.evict(|_| false)), ["a.jpg", "b.jpg"]);
+        // Fits its size now.
+        assert!(cache.evict(|_| false).is_empty());
+        let size: u64 = cache.entries.values().map(|entry| entry.size).sum();
+        assert_eq!(size, 15);
+    }
+
+    #[test]
+    fn keeps_pinned_evolutions() {
+        let (_folder, mut cache) = cache(5, &[("a", 10, 1), ("b", 10, 2), ("c", 10, 3)]);
+        let pinned = cache.path("a", "evolution.jpg");
+        assert_eq!(
+            file_names(&cache.evict(|path| path == pinned)),
+            ["b.jpg", "c.jpg"]
+        );
+        assert!(cache.contains(&pinned));
+        // Stays over its size while pinned.
+        assert!(cache.evict(|path| path == pinned).is_empty());
+        assert_eq!(file_names(&cache.evict(|_| false)), ["a.jpg"]);
+    }
+}
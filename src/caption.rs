@@ -0,0 +1,441 @@
+use ab_glyph::{FontVec, PxScale};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Local};
+use image::{GrayImage, Luma, RgbImage};
+use imageproc::drawing::{draw_text_mut, text_size};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+// Fonts tried when no font is given, the first one which exists is used.
+const SYSTEM_FONTS: [&str; 6] = [
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+// Details are written smaller than the prompt.
+const DETAILS_SCALE: f32 = 0.75;
+
+/// Details of a pollen which can be written on the wallpaper.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaptionField {
+    Prompt,
+    Model,
+    /// Date the wallpaper was set.
+    Date,
+}
+
+impl FromStr for CaptionField {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "prompt" => Ok(CaptionField::Prompt),
+            "model" => Ok(CaptionField::Model),
+            "date" => Ok(CaptionField::Date),
+            _ => bail!(
+                "Unknown caption field \"{}\", expected one of \"prompt\", \"model\" or \"date\"",
+                s
+            ),
+        }
+    }
+}
+
+/// Corner or edge of the image the caption is anchored to.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CaptionPosition {
+    TopLeft,
+    Top,
+    TopRight,
+    #[default]
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl CaptionPosition {
+    fn is_top(&self) -> bool {
+        matches!(
+            self,
+            CaptionPosition::TopLeft | CaptionPosition::Top | CaptionPosition::TopRight
+        )
+    }
+}
+
+impl FromStr for CaptionPosition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "top-left" => Ok(CaptionPosition::TopLeft),
+            "top" => Ok(CaptionPosition::Top),
+            "top-right" => Ok(CaptionPosition::TopRight),
+            "bottom-left" => Ok(CaptionPosition::BottomLeft),
+            "bottom" => Ok(CaptionPosition::Bottom),
+            "bottom-right" => Ok(CaptionPosition::BottomRight),
+            _ => bail!(
+                "Unknown caption position \"{}\", expected one of \"top-left\", \"top\", \"top-right\", \"bottom-left\", \"bottom\" or \"bottom-right\"",
+                s
+            ),
+        }
+    }
+}
+
+/// What the caption says about the pollen.
+#[derive(Debug, Clone)]
+pub struct CaptionInfo {
+    pub prompt: Option<String>,
+    pub model: Option<String>,
+    pub date: SystemTime,
+}
+
+/// How the caption looks.
+#[derive(Debug, Clone)]
+pub struct CaptionStyle {
+    /// The prompt goes on the first line, the other fields on the second.
+    pub fields: Vec<CaptionField>,
+    pub font_path: PathBuf,
+    pub position: CaptionPosition,
+    /// Height of the prompt line in pixels, relative to the image when not given.
+    pub size: Option<f32>,
+    /// Opacity of the text between 0 and 1.
+    pub opacity: f32,
+    /// Opacity of the dark box behind the text, 0 leaves it out.
+    pub box_opacity: f32,
+}
+
+impl CaptionStyle {
+    /// Uses the font at `font_path` or the first system font found.
+    pub fn new(
+        fields: Vec<CaptionField>,
+        font_path: Option<PathBuf>,
+        position: CaptionPosition,
+        size: Option<f32>,
+        opacity: f32,
+        box_opacity: f32,
+    ) -> Result<Self> {
+        if let Some(size) = size.filter(|size| *size <= 0.0) {
+            bail!("Caption size should be positive, got {}", size);
+        }
+        for opacity in [opacity, box_opacity] {
+            if !(0.0..=1.0).contains(&opacity) {
+                bail!("Caption opacity should be between 0 and 1, got {}", opacity);
+            }
+        }
+        let font_path = match font_path {
+            Some(font_path) => font_path,
+            None => SYSTEM_FONTS
+                .iter()
+                .map(PathBuf::from)
+                .find(|path| path.exists())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No font found for the caption, give one with \"--caption-font <path>\""
+                    )
+                })?,
+        };
+        // Fail early if the font is unusable.
+        load_font(&font_path)?;
+        Ok(Self {
+            fields,
+            font_path,
+            position,
+            size,
+            opacity,
+            box_opacity,
+        })
+    }
+
+    /// The prompt and the details lines, fields which the pollen doesn't have are left out.
+    fn lines(&self, info: &CaptionInfo) -> (Option<String>, Option<String>) {
+        let prompt = info
+            .prompt
+            .as_deref()
+            .map(str::trim)
+            .filter(|prompt| self.fields.contains(&CaptionField::Prompt) && !prompt.is_empty())
+            .map(str::to_owned);
+        let details: Vec<String> = self
+            .fields
+            .iter()
+            .filter_map(|field| match field {
+                CaptionField::Prompt => None,
+                CaptionField::Model => info.model.clone(),
+                CaptionField::Date => Some(date(info.date)),
+            })
+            .collect();
+        let details = Some(details.join(" · ")).filter(|details| !details.is_empty());
+        (prompt, details)
+    }
+
+    /// Writes the caption on the image.
+    pub fn draw(&self, image: &mut RgbImage, info: &CaptionInfo) -> Result<()> {
+        let (prompt, details) = self.lines(info);
+        if prompt.is_none() && details.is_none() {
+            return Ok(());
+        }
+        let font = load_font(&self.font_path)?;
+        let (width, height) = image.dimensions();
+        let prompt_scale = self.size.unwrap_or((height as f32 / 40.0).max(12.0));
+        let padding = (prompt_scale / 2.0) as u32;
+        let max_text_width = width.saturating_sub(4 * padding);
+
+        let lines: Vec<(PxScale, String)> = [
+            (prompt_scale, prompt),
+            (prompt_scale * DETAILS_SCALE, details),
+        ]
+        .into_iter()
+        .filter_map(|(scale, line)| {
+            let scale = PxScale::from(scale);
+            Some((scale, shorten(&font, scale, &line?, max_text_width)))
+        })
+        .collect();
+
+        // Render the text as a coverage mask and blend it with the box onto the image.
+        let line_gap = padding / 2;
+        let sizes: Vec<(u32, u32)> = lines
+            .iter()
+            .map(|(scale, line)| text_size(*scale, &font, line))
+            .collect();
+        let text_width = sizes.iter().map(|(width, _)| *width).max().unwrap_or(0);
+        let text_height = sizes.iter().map(|(_, height)| *height).sum::<u32>()
+            + line_gap * (lines.len() as u32 - 1);
+        let box_width = (text_width + 2 * padding).min(width);
+        let box_height = (text_height + 2 * padding).min(height);
+
+        let mut mask = GrayImage::new(box_width, box_height);
+        let mut y = padding;
+        for ((scale, line), (_, line_height)) in lines.iter().zip(&sizes) {
+            draw_text_mut(
+                &mut mask,
+                Luma([255]),
+                padding as i32,
+                y as i32,
+                *scale,
+                &font,
+                line,
+            );
+            y += line_height + line_gap;
+        }
+
+        let margin = padding;
+        let box_x = match self.position {
+            CaptionPosition::TopLeft | CaptionPosition::BottomLeft => margin,
+            CaptionPosition::Top | CaptionPosition::Bottom => (width - box_width) / 2,
+            CaptionPosition::TopRight | CaptionPosition::BottomRight => {
+                width.saturating_sub(box_width + margin)
+            }
+        };
+        let box_y = if self.position.is_top() {
+            margin
+        } else {
+            height.saturating_sub(box_height + margin)
+        };
+
+        for (x, y, coverage) in mask.enumerate_pixels() {
+            let (image_x, image_y) = (box_x + x, box_y + y);
+            if image_x >= width || image_y >= height {
+                continue;
+            }
+            let text_alpha = coverage.0[0] as f32 / 255.0 * self.opacity;
+            let pixel = image.get_pixel_mut(image_x, image_y);
+            pixel.0 = pixel.0.map(|channel| {
+                let shaded = channel as f32 * (1.0 - self.box_opacity);
+                (shaded + (255.0 - shaded) * text_alpha).round() as u8
+            });
+        }
+        Ok(())
+    }
+}
+
+fn load_font(path: &Path) -> Result<FontVec> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("Couldn't read font \"{}\"", path.display()))?;
+    FontVec::try_from_vec(data).map_err(|_| anyhow!("Invalid font \"{}\"", path.display()))
+}
+
+/// Cuts the text with an ellipsis until it fits the width.
+fn shorten(font: &FontVec, scale: PxScale, text: &str, max_width: u32) -> String {
+    let mut shortened = text.to_owned();
+    let mut length = text.chars().count();
+    while length > 0 && text_size(scale, font, &shortened).0 > max_width {
+        length -= 1;
+        shortened = text.chars().take(length).collect::<String>() + "…";
+    }
+    shortened
+}
+
+/// `YYYY-MM-DD` of the time where the user is.
+fn date(time: SystemTime) -> String {
+    DateTime::<Local>::from(time).date_naive().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(fields: Vec<CaptionField>) -> CaptionStyle {
+        CaptionStyle {
+            fields,
+            font_path: PathBuf::new(),
+            position: CaptionPosition::default(),
+            size: None,
+            opacity: 1.0,
+            box_opacity: 0.5,
+        }
+    }
+
+    fn info(prompt: Option<&str>, model: Option<&str>) -> CaptionInfo {
+        CaptionInfo {
+            prompt: prompt.map(str::to_owned),
+            model: model.map(str::to_owned),
+            date: SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    // Tests which measure text are left out where there is no system font.
+    fn system_font() -> Option<FontVec> {
+        SYSTEM_FONTS
+            .iter()
+            .find_map(|path| load_font(Path::new(path)).ok())
+    }
+
+    #[test]
+    fn writes_the_chosen_fields() {
+        use CaptionField::{Date, Model, Prompt};
+        let date = date(SystemTime::UNIX_EPOCH);
+        let pollen = info(Some("  a lake at dawn "), Some("vit_b32"));
+        let lines = |fields| style(fields).lines(&pollen);
+
+        assert_eq!(
+            lines(vec![Prompt, Model, Date]),
+            (
+                Some("a lake at dawn".to_owned()),
+                Some(format!("vit_b32 · {}", date))
+            )
+        );
+        assert_eq!(
+            lines(vec![Date, Model]),
+            (None, Some(format!("{} · vit_b32", date)))
+        );
+        assert_eq!(
+            lines(vec![Prompt]),
+            (Some("a lake at dawn".to_owned()), None)
+        );
+        assert_eq!(lines(vec![]), (None, None));
+
+        // Fields the pollen doesn't have are left out.
+        let style = style(vec![Prompt, Model]);
+        assert_eq!(style.lines(&info(Some(" "), None)), (None, None));
+        assert_eq!(
+            style.lines(&info(None, Some("wiki_art"))),
+            (None, Some("wiki_art".to_owned()))
+        );
+    }
+
+    #[test]
+    fn shortens_with_an_ellipsis() {
+        let font = match system_font() {
+            Some(font) => font,
+            None => return,
+        };
+        let scale = PxScale::from(20.0);
+        let text = "a lake at dawn, mist over the water";
+        let width = text_size(scale, &font, text).0;
+        assert_eq!(shorten(&font, scale, text, width), text);
+
+        let shortened = shorten(&font, scale, text, width / 2);
+        assert!(shortened.ends_with('…'));
+        assert!(text.starts_with(shortened.trim_end_matches('…')));
+        assert!(text_size(scale, &font, &shortened).0 <= width / 2);
+        // Only the ellipsis is left when nothing fits.
+        assert_eq!(shorten(&font, scale, text, 0), "…");
+    }
+
+    #[test]
+    fn draws_the_box_at_its_position() {
+        let font_path = match SYSTEM_FONTS.iter().find(|path| Path::new(path).exists()) {
+            Some(font_path) => PathBuf::from(font_path),
+            None => return,
+        };
+        let is_boxed = |position, x, y| {
+            // An opaque box without text, 10 pixels from the edges.
+            let style = CaptionStyle {
+                font_path: font_path.clone(),
+                position,
+                size: Some(20.0),
+                opacity: 0.0,
+                box_opacity: 1.0,
+                ..style(vec![CaptionField::Prompt])
+            };
+            let mut image = RgbImage::from_pixel(400, 200, image::Rgb([255; 3]));
+            style.draw(&mut image, &info(Some("lake"), None)).unwrap();
+            image.get_pixel(x, y).0 == [0; 3]
+        };
+        let corners = [
+            (11, 11),
+            (200, 11),
+            (388, 11),
+            (11, 188),
+            (200, 188),
+            (388, 188),
+        ];
+        for (position, boxed) in [
+            (CaptionPosition::TopLeft, 0),
+            (CaptionPosition::Top, 1),
+            (CaptionPosition::TopRight, 2),
+            (CaptionPosition::BottomLeft, 3),
+            (CaptionPosition::Bottom, 4),
+            (CaptionPosition::BottomRight, 5),
+        ] {
+            for (index, (x, y)) in corners.into_iter().enumerate() {
+                assert_eq!(
+                    is_boxed(position, x, y),
+                    index == boxed,
+                    "{:?} {} {}",
+                    position,
+                    x,
+                    y
+                );
+            }
+            // Nothing is drawn on the margin.
+            assert!(!is_boxed(position, 5, 5) && !is_boxed(position, 394, 194));
+        }
+    }
+
+    #[test]
+    fn parses_positions_and_fields() {
+        assert_eq!(
+            "top-right".parse::<CaptionPosition>().unwrap(),
+            CaptionPosition::TopRight
+        );
+        assert!("center".parse::<CaptionPosition>().is_err());
+        assert_eq!("date".parse::<CaptionField>().unwrap(), CaptionField::Date);
+        assert!("seed".parse::<CaptionField>().is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_sizes_and_opacities() {
+        let new = |size, opacity, box_opacity| {
+            CaptionStyle::new(
+                vec![CaptionField::Prompt],
+                Some(PathBuf::from("/missing/font.ttf")),
+                CaptionPosition::Bottom,
+                size,
+                opacity,
+                box_opacity,
+            )
+            .unwrap_err()
+            .to_string()
+        };
+        assert!(new(Some(0.0), 1.0, 0.5).contains("size"));
+        assert!(new(None, 1.5, 0.5).contains("opacity"));
+        assert!(new(None, 1.0, -0.1).contains("opacity"));
+        // The bounds are valid, so the missing font is what fails.
+        assert!(new(Some(24.0), 0.0, 1.0).contains("font"));
+    }
+}
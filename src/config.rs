@@ -0,0 +1,28 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+pub const CONFIG_FILE_NAME: &str = "config";
+
+/// Command line options from `~/.pollenwall/config`, shell quoted with `#` comments.
+///
+/// They come before the options given on the command line, which override them.
+/// A missing file has no options.
+pub fn read(app_folder_path: &Path) -> Result<Vec<String>> {
+    let path = app_folder_path.join(CONFIG_FILE_NAME);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Couldn't read \"{}\"", path.display()))
+        }
+    };
+    let mut args = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        args.extend(
+            shell_words::split(line).with_context(|| {
+                format!("Invalid line {} of \"{}\"", number + 1, path.display())
+            })?,
+        );
+    }
+    Ok(args)
+}
@@ -0,0 +1,441 @@
+use crate::{attach::AttachSelector, history::HistoryEntry};
+use anyhow::Result;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Cell, List, ListItem, Paragraph, Row, Table, TableState},
+    DefaultTerminal, Frame,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, watch};
+use tokio_stream::StreamExt;
+
+// Redraw at least this often so that ages stay fresh.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Requests the dashboard sends to the pollen tracker.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Follow the processing pollen which the selector picks.
+    Attach(AttachSelector),
+    /// Detach from the attached pollen and don't attach to it again.
+    Skip,
+    /// Toggle the favourite flag of the current wallpaper.
+    Favourite,
+    /// Toggle setting new wallpapers.
+    Pause,
+    Quit,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcessingPollen {
+    pub uuid: String,
+    pub prompt: Option<String>,
+    pub model: Option<&'static str>,
+    pub evolution: Option<u64>,
+}
+
+/// What the dashboard shows, published by the pollen tracker on every change.
+#[derive(Debug, Clone, Default)]
+pub struct DashboardState {
+    pub connected: bool,
+    pub last_heartbeat_at: Option<SystemTime>,
+    pub attach_mode: bool,
+    pub attach_selector: AttachSelector,
+    pub paused: bool,
+    pub attached: Option<String>,
+    pub processing: Vec<ProcessingPollen>,
+    pub history: Vec<HistoryEntry>,
+}
+
+/// Runs the full screen dashboard until the user quits or the tracker goes away.
+pub async fn run(
+    mut state: watch::Receiver<DashboardState>,
+    commands: mpsc::UnboundedSender<Command>,
+) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let _restore = RestoreTerminal;
+    event_loop(&mut terminal, &mut state, &commands).await
+}
+
+/// Leaves raw mode and the alternate screen when dropped, even if the dashboard panics.
+struct RestoreTerminal;
+
+impl Drop for RestoreTerminal {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    state: &mut watch::Receiver<DashboardState>,
+    commands: &mpsc::UnboundedSender<Command>,
+) -> Result<()> {
+    let mut events = EventStream::new();
+    let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+    let mut table_state = TableState::default().with_selected(Some(0));
+    let mut keyword: Option<String> = None;
+
+    loop {
+        {
+            let state = state.borrow();
+            terminal.draw(|frame| draw(frame, &state, &mut table_state, keyword.as_deref()))?;
+        }
+
+        tokio::select! {
+            changed = state.changed() => {
+                if changed.is_err() {
+                    // Tracker stopped.
+                    return Ok(());
+                }
+            }
+            event = events.next() => match event {
+                Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                    let command = handle_press(
+                        key,
+                        &mut keyword,
+                        &state.borrow().processing,
+                        &mut table_state,
+                    );
+                    if let Some(command) = command {
+                        let quit = command == Command::Quit;
+                        if commands.send(command).is_err() || quit {
+                            return Ok(());
+                        }
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(err.into()),
+                None => return Ok(()),
+            },
+            _ = refresh.tick() => {}
+        }
+    }
+}
+
+/// Handles a key press, `keyword` is the prompt keyword while it is being typed after `/`.
+fn handle_press(
+    key: KeyEvent,
+    keyword: &mut Option<String>,
+    processing: &[ProcessingPollen],
+    table_state: &mut TableState,
+) -> Option<Command> {
+    match keyword.as_mut() {
+        Some(input) => match handle_keyword_key(key, input) {
+            Some(command) => {
+                *keyword = None;
+                command
+            }
+            None => None,
+        },
+        None if key.code == KeyCode::Char('/') => {
+            *keyword = Some(String::new());
+            None
+        }
+        None => handle_key(key, processing, table_state),
+    }
+}
+
+fn handle_key(
+    key: KeyEvent,
+    processing: &[ProcessingPollen],
+    table_state: &mut TableState,
+) -> Option<Command> {
+    match key.code {
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Some(Command::Quit),
+        KeyCode::Char('q') | KeyCode::Esc => Some(Command::Quit),
+        KeyCode::Up | KeyCode::Char('k') => {
+            table_state.select_previous();
+            None
+        }
+        KeyCode::Down | KeyCode::Char('j') => {
+            table_state.select_next();
+            None
+        }
+        KeyCode::Char('a') | KeyCode::Enter => table_state
+            .selected()
+            .and_then(|index| processing.get(index))
+            .map(|pollen| Command::Attach(AttachSelector::Uuid(pollen.uuid.clone()))),
+        KeyCode::Char('r') => Some(Command::Attach(AttachSelector::MostRecent)),
+        KeyCode::Char('s') => Some(Command::Skip),
+        KeyCode::Char('f') => Some(Command::Favourite),
+        KeyCode::Char('p') => Some(Command::Pause),
+        _ => None,
+    }
+}
+
+/// Edits the prompt keyword, returns the command once it is entered.
+///
+/// Escape cancels with the current selector, an empty keyword attaches to the first pollen.
+fn handle_keyword_key(key: KeyEvent, input: &mut String) -> Option<Option<Command>> {
+    match key.code {
+        KeyCode::Enter if input.trim().is_empty() => {
+            Some(Some(Command::Attach(AttachSelector::First)))
+        }
+        KeyCode::Enter => Some(Some(Command::Attach(AttachSelector::Prompt(
+            input.trim().to_lowercase(),
+        )))),
+        KeyCode::Esc => Some(None),
+        KeyCode::Backspace => {
+            input.pop();
+            None
+        }
+        KeyCode::Char(c) => {
+            input.push(c);
+            None
+        }
+        _ => None,
+    }
+}
+
+fn secs_since(time: SystemTime) -> u64 {
+    SystemTime::now()
+        .duration_since(time)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn draw(
+    frame: &mut Frame,
+    state: &DashboardState,
+    table_state: &mut TableState,
+    keyword: Option<&str>,
+) {
+    let [status_area, processing_area, history_area, keys_area] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(5),
+        Constraint::Length(12),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    draw_status(frame, state, status_area);
+    draw_processing(frame, state, table_state, processing_area);
+    draw_history(frame, state, history_area);
+
+    let keys = match keyword {
+        Some(keyword) => Line::from(vec![
+            Span::raw("attach to prompt containing: "),
+            Span::raw(keyword).bold(),
+            Span::raw("▏"),
+        ]),
+        None => Line::from(
+            "↑/↓ select  a attach  r latest  / prompt  s skip  f favourite  p pause  q quit"
+                .dark_gray(),
+        ),
+    };
+    frame.render_widget(Paragraph::new(keys), keys_area);
+}
+
+fn draw_status(frame: &mut Frame, state: &DashboardState, area: Rect) {
+    let connection = if state.connected {
+        "connected".green()
+    } else {
+        "reconnecting".red()
+    };
+    let heartbeat = match state.last_heartbeat_at {
+        Some(at) => format!("{}s ago", secs_since(at)),
+        None => "never".into(),
+    };
+    let mode = if state.attach_mode {
+        format!("attach ({})", state.attach_selector)
+    } else {
+        "all".into()
+    };
+
+    let mut spans = vec![
+        Span::raw("node: "),
+        connection,
+        Span::raw(format!("  heartbeat: {}  mode: {}", heartbeat, mode)),
+    ];
+    if state.paused {
+        spans.push(Span::raw("  "));
+        spans.push("paused".yellow().bold());
+    }
+
+    frame.render_widget(
+        Paragraph::new(Line::from(spans)).block(Block::bordered().title(" pollenwall ")),
+        area,
+    );
+}
+
+fn draw_processing(
+    frame: &mut Frame,
+    state: &DashboardState,
+    table_state: &mut TableState,
+    area: Rect,
+) {
+    let rows = state.processing.iter().map(|pollen| {
+        let attached = state.attached.as_deref() == Some(pollen.uuid.as_str());
+        let row = Row::new(vec![
+            Cell::from(if attached { "●" } else { "" }),
+            Cell::from(pollen.uuid.chars().take(12).collect::<String>()),
+            Cell::from(pollen.model.unwrap_or("-")),
+            Cell::from(
+                pollen
+                    .evolution
+                    .map_or_else(|| "-".to_string(), |number| number.to_string()),
+            ),
+            Cell::from(pollen.prompt.clone().unwrap_or_default()),
+        ]);
+        if attached {
+            row.style(
+                Style::default()
+                    .fg(Color::Green)
+                    .add_modifier(Modifier::BOLD),
+            )
+        } else {
+            row
+        }
+    });
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(1),
+            Constraint::Length(12),
+            Constraint::Length(16),
+            Constraint::Length(9),
+            Constraint::Fill(1),
+        ],
+    )
+    .header(Row::new(vec!["", "pollen", "model", "evolution", "prompt"]).bold())
+    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+    .block(Block::bordered().title(format!(" processing pollens ({}) ", state.processing.len())));
+
+    frame.render_stateful_widget(table, area, table_state);
+}
+
+fn draw_history(frame: &mut Frame, state: &DashboardState, area: Rect) {
+    let items = state.history.iter().map(|entry| {
+        let set_at = UNIX_EPOCH + Duration::from_secs(entry.set_at);
+        ListItem::new(Line::from(vec![
+            if entry.favourite {
+                "★ ".yellow()
+            } else {
+                Span::raw("  ")
+            },
+            format!("{:>6}s ago  ", secs_since(set_at)).dark_gray(),
+            Span::raw(format!(
+                "{:<16} {}",
+                entry.model.as_deref().unwrap_or("-"),
+                entry.prompt.as_deref().unwrap_or_default()
+            )),
+        ]))
+    });
+
+    frame.render_widget(
+        List::new(items).block(Block::bordered().title(" recent wallpapers ")),
+        area,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pollen(uuid: &str) -> ProcessingPollen {
+        ProcessingPollen {
+            uuid: uuid.to_owned(),
+            prompt: None,
+            model: None,
+            evolution: None,
+        }
+    }
+
+    // Presses the keys in order and returns the commands they sent.
+    fn press(keys: &[KeyCode], keyword: &mut Option<String>) -> Vec<Command> {
+        let processing = [pollen("first"), pollen("second")];
+        let mut table_state = TableState::default().with_selected(Some(0));
+        keys.iter()
+            .filter_map(|code| {
+                let key = KeyEvent::new(*code, KeyModifiers::NONE);
+                handle_press(key, keyword, &processing, &mut table_state)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sends_commands() {
+        let mut keyword = None;
+        assert_eq!(press(&[KeyCode::Char('q')], &mut keyword), [Command::Quit]);
+        assert_eq!(press(&[KeyCode::Esc], &mut keyword), [Command::Quit]);
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        let mut table_state = TableState::default();
+        assert_eq!(
+            handle_key(ctrl_c, &[], &mut table_state),
+            Some(Command::Quit)
+        );
+        assert_eq!(press(&[KeyCode::Char('s')], &mut keyword), [Command::Skip]);
+        assert_eq!(
+            press(&[KeyCode::Char('f')], &mut keyword),
+            [Command::Favourite]
+        );
+        assert_eq!(press(&[KeyCode::Char('p')], &mut keyword), [Command::Pause]);
+        assert_eq!(
+            press(&[KeyCode::Char('r')], &mut keyword),
+            [Command::Attach(AttachSelector::MostRecent)]
+        );
+        assert!(press(&[KeyCode::Char('x')], &mut keyword).is_empty());
+    }
+
+    #[test]
+    fn attaches_the_selected_pollen() {
+        let mut keyword = None;
+        let attach = |uuid: &str| Command::Attach(AttachSelector::Uuid(uuid.to_owned()));
+        assert_eq!(press(&[KeyCode::Enter], &mut keyword), [attach("first")]);
+        assert_eq!(
+            press(&[KeyCode::Down, KeyCode::Char('a')], &mut keyword),
+            [attach("second")]
+        );
+        assert_eq!(
+            press(
+                &[KeyCode::Char('j'), KeyCode::Char('k'), KeyCode::Enter],
+                &mut keyword
+            ),
+            [attach("first")]
+        );
+    }
+
+    #[test]
+    fn edits_the_prompt_keyword() {
+        let mut keyword = None;
+        // Keys are typed into the keyword instead of sending commands.
+        assert!(press(&[KeyCode::Char('/'), KeyCode::Char('Q')], &mut keyword).is_empty());
+        assert_eq!(keyword.as_deref(), Some("Q"));
+        assert!(press(&[KeyCode::Backspace, KeyCode::Char('L')], &mut keyword).is_empty());
+        assert_eq!(keyword.as_deref(), Some("L"));
+        assert_eq!(
+            press(
+                &[KeyCode::Char('a'), KeyCode::Char('k'), KeyCode::Enter],
+                &mut keyword
+            ),
+            [Command::Attach(AttachSelector::Prompt("lak".to_owned()))]
+        );
+        assert_eq!(keyword, None);
+
+        // Escape leaves without a command.
+        assert!(press(
+            &[KeyCode::Char('/'), KeyCode::Char('s'), KeyCode::Esc],
+            &mut keyword
+        )
+        .is_empty());
+        assert_eq!(keyword, None);
+        assert_eq!(press(&[KeyCode::Char('q')], &mut keyword), [Command::Quit]);
+    }
+
+    #[test]
+    fn attaches_the_first_pollen_without_a_keyword() {
+        let mut keyword = None;
+        // Backspace on an empty keyword leaves it empty.
+        assert!(press(&[KeyCode::Char('/'), KeyCode::Backspace], &mut keyword).is_empty());
+        assert_eq!(keyword.as_deref(), Some(""));
+        assert_eq!(
+            press(&[KeyCode::Char(' '), KeyCode::Enter], &mut keyword),
+            [Command::Attach(AttachSelector::First)]
+        );
+    }
+}
@@ -0,0 +1,311 @@
+//! Evolutions are the numbered outputs a pollen emits while it is processing.
+//!
+//! Their file names follow the pattern `[<prefix>_]<number>.<extension>`, for example
+//! `processing_00005.jpg`. The prefix may contain anything including digits and underscores,
+//! the number is the run of ASCII digits right before the extension and the extension
+//! is one of the media types in [`MediaType`].
+
+use crate::media::{MediaPolicies, MediaPolicy, MediaType};
+use anyhow::{bail, Result};
+use ipfs_api::response::{FileLsResponse, IpfsHeader};
+use std::{fmt, path::Path, str::FromStr};
+
+/// Parsed name of an evolution.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EvolutionName {
+    pub number: u64,
+    pub media_type: MediaType,
+}
+
+/// Parses an evolution file name, `None` if it doesn't follow the pattern.
+pub fn parse(name: &str) -> Option<EvolutionName> {
+    let media_type = MediaType::from_name(name)?;
+    let stem = Path::new(name).file_stem()?.to_str()?;
+    let digits_start = stem
+        .rfind(|c: char| !c.is_ascii_digit())
+        .map_or(0, |index| index + 1);
+    let (prefix, digits) = stem.split_at(digits_start);
+    if digits.is_empty() || !(prefix.is_empty() || prefix.ends_with('_')) {
+        return None;
+    }
+    Some(EvolutionName {
+        // Fails only when the number is too large which we don't consider an evolution.
+        number: digits.parse().ok()?,
+        media_type,
+    })
+}
+
+/// Which evolution of a pollen is set as wallpaper.
+///
+/// Written as `latest`, `final`, `step:<number>` or `every:<number>`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EvolutionStrategy {
+    /// The latest evolution every time a new one arrives.
+    #[default]
+    Latest,
+    /// Only the last evolution once the pollen is done.
+    FinalOnly,
+    /// The evolution with this number, the last one if the pollen finishes before reaching it.
+    Step(u64),
+    /// Every evolution whose number is a multiple of this and the last one.
+    EveryNth(u64),
+}
+
+impl FromStr for EvolutionStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let strategy = match s.split_once(':') {
+            None if s == "latest" => EvolutionStrategy::Latest,
+            None if s == "final" => EvolutionStrategy::FinalOnly,
+            Some(("step", number)) => EvolutionStrategy::Step(number.parse()?),
+            Some(("every", number)) => match number.parse()? {
+                0 => bail!("Evolution interval should be a positive number"),
+                number => EvolutionStrategy::EveryNth(number),
+            },
+            _ => bail!(
+                "Invalid evolution strategy \"{}\", expected one of \"latest\", \"final\", \"step:<number>\" or \"every:<number>\"",
+                s
+            ),
+        };
+        Ok(strategy)
+    }
+}
+
+impl fmt::Display for EvolutionStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvolutionStrategy::Latest => write!(f, "latest"),
+            EvolutionStrategy::FinalOnly => write!(f, "final"),
+            EvolutionStrategy::Step(number) => write!(f, "step:{}", number),
+            EvolutionStrategy::EveryNth(number) => write!(f, "every:{}", number),
+        }
+    }
+}
+
+/// Evolutions in the output listing in the formats which aren't skipped, ordered by number.
+///
+/// Evolutions with the same number are ordered by name so the order doesn't depend on the listing.
+pub fn evolutions<'a>(
+    output: &'a FileLsResponse,
+    media_policies: &MediaPolicies,
+) -> Vec<(EvolutionName, &'a IpfsHeader)> {
+    let mut evolutions: Vec<(EvolutionName, &IpfsHeader)> = output
+        .objects
+        .values()
+        .flat_map(|object| &object.links)
+        .filter(|header| media_policies.policy(&header.name) != MediaPolicy::Skip)
+        .filter_map(|header| Some((parse(&header.name)?, header)))
+        .collect();
+    evolutions.sort_by(|(a, a_header), (b, b_header)| {
+        a.number
+            .cmp(&b.number)
+            .then_with(|| a_header.name.cmp(&b_header.name))
+    });
+    evolutions
+}
+
+/// Picks the evolution to set as wallpaper, `None` if it isn't time for one yet.
+///
+/// `evolutions` are ordered as [`evolutions`] returns them.
+pub fn select<'a>(
+    evolutions: &[(EvolutionName, &'a IpfsHeader)],
+    strategy: EvolutionStrategy,
+    done: bool,
+) -> Option<&'a IpfsHeader> {
+    let latest = evolutions.last().map(|(_, header)| *header);
+    match strategy {
+        EvolutionStrategy::Latest => latest,
+        EvolutionStrategy::FinalOnly => latest.filter(|_| done),
+        EvolutionStrategy::Step(step) => evolutions
+            .iter()
+            .rev()
+            .find(|(name, _)| name.number == step)
+            .map(|(_, header)| *header)
+            .or_else(|| latest.filter(|_| done)),
+        EvolutionStrategy::EveryNth(_) if done => latest,
+        EvolutionStrategy::EveryNth(interval) => evolutions
+            .iter()
+            .rev()
+            .find(|(name, _)| name.number % interval == 0)
+            .map(|(_, header)| *header),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::collections::HashMap;
+
+    const EXTENSIONS: [&str; 7] = ["jpg", "jpeg", "png", "webp", "gif", "mp4", "webm"];
+
+    fn header(name: &str) -> IpfsHeader {
+        IpfsHeader {
+            name: name.to_owned(),
+            hash: format!("hash-{}", name),
+            size: 0,
+            typ: Some("File".to_owned()),
+        }
+    }
+
+    fn listing(names: &[String]) -> FileLsResponse {
+        let links = names.iter().map(|name| header(name)).collect();
+        let object = ipfs_api::response::IpfsDetailedFile {
+            hash: "output".to_owned(),
+            size: 0,
+            typ: "Directory".to_owned(),
+            links,
+        };
+        FileLsResponse {
+            arguments: HashMap::new(),
+            objects: HashMap::from([("output".to_owned(), object)]),
+        }
+    }
+
+    fn policies() -> MediaPolicies {
+        MediaPolicies::new(std::iter::empty(), true).unwrap()
+    }
+
+    fn selected_name(names: &[String], strategy: EvolutionStrategy, done: bool) -> Option<String> {
+        let output = listing(names);
+        let policies = policies();
+        let evolutions = evolutions(&output, &policies);
+        select(&evolutions, strategy, done).map(|header| header.name.clone())
+    }
+
+    #[test]
+    fn parses_names() {
+        let number = |name| parse(name).map(|evolution| evolution.number);
+        assert_eq!(number("processing_00005.jpg"), Some(5));
+        assert_eq!(number("00042.png"), Some(42));
+        assert_eq!(number("run2_step_00010.jpg"), Some(10));
+        assert_eq!(number("processing_00000.JPG"), Some(0));
+        assert_eq!(number("processing_.jpg"), None);
+        assert_eq!(number("image5.jpg"), None);
+        assert_eq!(number("processing_00005.txt"), None);
+        assert_eq!(number("processing_00005"), None);
+        assert_eq!(number("processing_99999999999999999999999.jpg"), None);
+        assert_eq!(number(""), None);
+    }
+
+    #[test]
+    fn selects_with_strategies() {
+        let names: Vec<String> = (0..=7)
+            .map(|n| format!("processing_{:05}.jpg", n))
+            .collect();
+        let name = |n: u64| Some(format!("processing_{:05}.jpg", n));
+
+        assert_eq!(
+            selected_name(&names, EvolutionStrategy::Latest, false),
+            name(7)
+        );
+        assert_eq!(
+            selected_name(&names, EvolutionStrategy::FinalOnly, false),
+            None
+        );
+        assert_eq!(
+            selected_name(&names, EvolutionStrategy::FinalOnly, true),
+            name(7)
+        );
+        assert_eq!(
+            selected_name(&names, EvolutionStrategy::Step(3), false),
+            name(3)
+        );
+        assert_eq!(
+            selected_name(&names, EvolutionStrategy::Step(9), false),
+            None
+        );
+        assert_eq!(
+            selected_name(&names, EvolutionStrategy::Step(9), true),
+            name(7)
+        );
+        assert_eq!(
+            selected_name(&names, EvolutionStrategy::EveryNth(3), false),
+            name(6)
+        );
+        assert_eq!(
+            selected_name(&names, EvolutionStrategy::EveryNth(3), true),
+            name(7)
+        );
+        assert_eq!(selected_name(&[], EvolutionStrategy::Latest, true), None);
+    }
+
+    #[test]
+    fn parses_strategies() {
+        for strategy in ["latest", "final", "step:12", "every:5"] {
+            assert_eq!(
+                strategy.parse::<EvolutionStrategy>().unwrap().to_string(),
+                strategy
+            );
+        }
+        assert!("every:0".parse::<EvolutionStrategy>().is_err());
+        assert!("step:".parse::<EvolutionStrategy>().is_err());
+        assert!("first".parse::<EvolutionStrategy>().is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn parse_never_panics(name in ".*") {
+            let _ = parse(&name);
+        }
+
+        #[test]
+        fn parses_the_number_before_the_extension(
+            prefix in "[^/.]*",
+            number in 0_u64..=u32::MAX as u64,
+            width in 0_usize..12,
+            extension in prop::sample::select(EXTENSIONS.to_vec()),
+        ) {
+            let name = format!("{}_{:0width$}.{}", prefix, number, extension, width = width);
+            prop_assert_eq!(parse(&name).map(|evolution| evolution.number), Some(number));
+        }
+
+        #[test]
+        fn unknown_extensions_are_never_evolutions(
+            stem in "[^/]*",
+            extension in "[a-z0-9]{0,5}",
+        ) {
+            prop_assume!(!EXTENSIONS.contains(&extension.as_str()));
+            let name = format!("{}.{}", stem, extension);
+            prop_assert_eq!(parse(&name), None);
+        }
+
+        #[test]
+        fn latest_is_the_highest_number_regardless_of_order(
+            numbers in prop::collection::vec(0_u64..1000, 1..20),
+            others in prop::collection::vec("[a-z_0-9]{0,10}\\.(txt|json|log)", 0..5),
+            seed in any::<u64>(),
+        ) {
+            let mut names: Vec<String> = numbers
+                .iter()
+                .map(|number| format!("processing_{:05}.jpg", number))
+                .chain(others)
+                .collect();
+            // Shuffle deterministically so that the listing order varies.
+            names.sort_by_key(|name| {
+                name.bytes().fold(seed, |hash, byte| hash.rotate_left(5) ^ byte as u64)
+            });
+            let highest = numbers.iter().max().unwrap();
+            prop_assert_eq!(
+                selected_name(&names, EvolutionStrategy::Latest, false),
+                Some(format!("processing_{:05}.jpg", highest))
+            );
+        }
+
+        #[test]
+        fn ties_are_broken_by_name(number in 0_u64..1000, reversed in any::<bool>()) {
+            let mut names = vec![
+                format!("processing_{:05}.jpg", number),
+                format!("processing_{:05}.png", number),
+            ];
+            if reversed {
+                names.reverse();
+            }
+            prop_assert_eq!(
+                selected_name(&names, EvolutionStrategy::Latest, false),
+                Some(format!("processing_{:05}.png", number))
+            );
+        }
+    }
+}
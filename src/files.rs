@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// Tells apart the temporary files of writes which run at the same time.
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A path next to `path` to write to before renaming it over `path`.
+///
+/// Unique to the process and the call, so writes of the same file never share it.
+/// It ends with `.part` like the left over downloads which are cleaned up.
+pub fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(
+        ".{}.{}.{}.part",
+        file_name,
+        std::process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Writes the file so that a crash or another reader never sees it half written.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    let tmp_path = temp_path(path);
+    let result = std::fs::write(&tmp_path, contents).and_then(|_| std::fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result.with_context(|| format!("Couldn't write \"{}\"", path.display()))
+}
+
+/// A folder for the files of a test, removed with them when it is dropped.
+#[cfg(test)]
+pub struct TempFolder(PathBuf);
+
+#[cfg(test)]
+impl TempFolder {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "pollenwall-{}-{}-{}",
+            name,
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempFolder {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempFolder {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gives_unique_temp_paths() {
+        let path = Path::new("/tmp/state.json");
+        let first = temp_path(path);
+        let second = temp_path(path);
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), path.parent());
+        assert!(first.to_string_lossy().ends_with(".part"));
+    }
+
+    #[test]
+    fn writes_atomically() {
+        let folder_path = TempFolder::new("files");
+        let path = folder_path.join("state.json");
+
+        write_atomic(&path, "first").unwrap();
+        write_atomic(&path, "second").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+        // No temporary file is left behind.
+        assert_eq!(std::fs::read_dir(&*folder_path).unwrap().count(), 1);
+
+        // Nor when the write fails.
+        assert!(write_atomic(&folder_path.join("missing").join("state.json"), "third").is_err());
+        assert!(write_atomic(&folder_path, "fourth").is_err());
+        assert_eq!(std::fs::read_dir(&*folder_path).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,189 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Tracks the `HEARTBEAT` messages of the pubsub topics which tell that the connection is alive.
+#[derive(Debug)]
+pub struct Heartbeat {
+    timeout: Duration,
+    last: Option<Instant>,
+    last_received_at: Option<SystemTime>,
+    // Reference point for the staleness of the current subscription before any heartbeat arrives.
+    subscribed_at: Instant,
+    liveness: Liveness,
+}
+
+/// Whether a heartbeat arrived within the timeout, shared with the systemd watchdog task.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub struct Liveness {
+    timeout: Duration,
+    // The last heartbeat, or the start if none arrived yet.
+    last: Arc<Mutex<Instant>>,
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+impl Liveness {
+    pub fn is_alive(&self, now: Instant) -> bool {
+        self.last.lock().map_or(true, |last| {
+            now.saturating_duration_since(*last) <= self.timeout
+        })
+    }
+}
+
+impl Heartbeat {
+    pub fn new(timeout: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            timeout,
+            last: None,
+            last_received_at: None,
+            subscribed_at: now,
+            liveness: Liveness {
+                timeout,
+                last: Arc::new(Mutex::new(now)),
+            },
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Starts a new window for the subscription which is just made.
+    pub fn subscribed(&mut self, now: Instant) {
+        self.subscribed_at = now;
+    }
+
+    pub fn received(&mut self, now: Instant) {
+        self.last = Some(now);
+        self.last_received_at = Some(SystemTime::now());
+        if let Ok(mut last) = self.liveness.last.lock() {
+            *last = now;
+        }
+    }
+
+    /// Time passed since the last heartbeat, `None` if none received yet.
+    pub fn age(&self) -> Option<Duration> {
+        self.last.map(|last| last.elapsed())
+    }
+
+    pub fn last_received_at(&self) -> Option<SystemTime> {
+        self.last_received_at
+    }
+
+    /// The current subscription didn't receive a heartbeat within the timeout, it should be renewed.
+    pub fn is_stale(&self, now: Instant) -> bool {
+        let reference = match self.last {
+            Some(last) if last > self.subscribed_at => last,
+            _ => self.subscribed_at,
+        };
+        now.saturating_duration_since(reference) > self.timeout
+    }
+
+    /// A heartbeat arrived within the timeout regardless of reconnections.
+    ///
+    /// Unlike `is_stale`, reconnecting doesn't renew this so a connection which
+    /// keeps failing is eventually noticed by the service manager.
+    pub fn liveness(&self) -> Liveness {
+        self.liveness.clone()
+    }
+}
+
+/// Tells systemd that the service is alive while heartbeats arrive when it runs with `WatchdogSec` set.
+///
+/// Staying silent makes systemd restart the service once the watchdog timeout passes.
+/// It runs in a task of its own so that a message which takes long to handle, like a slow download,
+/// doesn't get a healthy service restarted.
+#[cfg(target_os = "linux")]
+pub fn spawn_watchdog(liveness: Liveness) {
+    let watchdog_timeout = match sd_notify::watchdog_enabled() {
+        Some(timeout) => timeout,
+        None => return,
+    };
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(watchdog_timeout / 2);
+        loop {
+            ticks.tick().await;
+            if !liveness.is_alive(Instant::now()) {
+                tracing::debug!("heartbeat is missing, holding back systemd watchdog notification");
+                continue;
+            }
+            if let Err(err) = sd_notify::notify(&[sd_notify::NotifyState::Watchdog]) {
+                tracing::warn!(error = %err, "failed to notify systemd watchdog");
+            }
+        }
+    });
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn spawn_watchdog(_liveness: Liveness) {}
+
+/// Tells systemd that `pollenwall` started when it runs as a `Type=notify` service.
+#[cfg(target_os = "linux")]
+pub fn notify_ready() {
+    if let Err(err) = sd_notify::notify(&[sd_notify::NotifyState::Ready]) {
+        tracing::warn!(error = %err, "failed to notify systemd of readiness");
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn notify_ready() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn goes_stale_without_heartbeats() {
+        let mut heartbeat = Heartbeat::new(TIMEOUT);
+        let start = Instant::now();
+        heartbeat.subscribed(start);
+        assert!(!heartbeat.is_stale(start + TIMEOUT));
+        assert!(heartbeat.is_stale(start + TIMEOUT + secs(1)));
+
+        heartbeat.received(start + secs(50));
+        assert!(!heartbeat.is_stale(start + secs(110)));
+        assert!(heartbeat.is_stale(start + secs(111)));
+    }
+
+    #[test]
+    fn gives_new_subscriptions_a_grace_window() {
+        let mut heartbeat = Heartbeat::new(TIMEOUT);
+        let start = Instant::now();
+        heartbeat.received(start);
+        // Renewed long after the last heartbeat, it waits for the timeout again.
+        heartbeat.subscribed(start + secs(100));
+        assert!(!heartbeat.is_stale(start + secs(160)));
+        assert!(heartbeat.is_stale(start + secs(161)));
+        // A heartbeat on the new subscription counts from when it arrived.
+        heartbeat.received(start + secs(150));
+        assert!(!heartbeat.is_stale(start + secs(210)));
+        assert!(heartbeat.is_stale(start + secs(211)));
+    }
+
+    #[test]
+    fn stays_alive_only_with_heartbeats() {
+        let mut heartbeat = Heartbeat::new(TIMEOUT);
+        let start = Instant::now();
+        heartbeat.received(start);
+        let liveness = heartbeat.liveness();
+        assert!(liveness.is_alive(start + TIMEOUT));
+        assert!(!liveness.is_alive(start + TIMEOUT + secs(1)));
+
+        // Resubscribing doesn't revive it, a heartbeat does.
+        heartbeat.subscribed(start + secs(100));
+        assert!(!liveness.is_alive(start + secs(100)));
+        heartbeat.received(start + secs(120));
+        assert!(liveness.is_alive(start + secs(120)));
+        assert!(liveness.is_alive(start + secs(180)));
+        assert!(!liveness.is_alive(start + secs(181)));
+    }
+}
@@ -0,0 +1,253 @@
+use crate::{files, retention::Retention};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tracing::warn;
+
+const HISTORY_FILE_NAME: &str = "history.json";
+// Favourites and entries whose files are kept are kept regardless of this limit.
+const MAX_HISTORY_ENTRIES: usize = 100;
+
+/// A pollen evolution which was set as wallpaper.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub pollen_uuid: String,
+    /// Ipfs hash of the evolution.
+    pub cid: String,
+    pub evolution: String,
+    pub path: PathBuf,
+    pub prompt: Option<String>,
+    pub model: Option<String>,
+    /// Seconds since unix epoch.
+    pub set_at: u64,
+    #[serde(default)]
+    pub favourite: bool,
+    /// Files written for the wallpaper, empty once the retention removed them.
+    #[serde(default)]
+    pub files: Vec<PathBuf>,
+}
+
+/// Wallpapers set so far, newest first, stored in `~/.pollenwall/history.json`.
+#[derive(Debug)]
+pub struct History {
+    path: PathBuf,
+    entries: VecDeque<HistoryEntry>,
+}
+
+impl History {
+    /// Loads the history from the app folder, a missing or unreadable file starts an empty history.
+    pub fn load(app_folder_path: &Path) -> Self {
+        let path = app_folder_path.join(HISTORY_FILE_NAME);
+        let mut entries: VecDeque<HistoryEntry> = match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|err| {
+                warn!(path = %path.display(), error = %err, "couldn't parse history, starting a new one");
+                VecDeque::new()
+            }),
+            Err(_) => VecDeque::new(),
+        };
+        // Entries from before files were recorded only know the wallpaper.
+        for entry in entries.iter_mut() {
+            if entry.files.is_empty() && entry.path.exists() {
+                entry.files.push(entry.path.clone());
+            }
+        }
+        Self { path, entries }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        files::write_atomic(&self.path, serde_json::to_vec_pretty(&self.entries)?)
+    }
+
+    pub fn push(&mut self, entry: HistoryEntry) {
+        self.entries.push_front(entry);
+
+        // Drop the oldest entries which are not favourites and have no files left.
+        let mut count = 0;
+        self.entries.retain(|entry| {
+            count += 1;
+            entry.favourite || !entry.files.is_empty() || count <= MAX_HISTORY_ENTRIES
+        });
+    }
+
+    /// Forgets the files of the wallpapers which the retention doesn't keep and returns them.
+    pub fn expire(&mut self, retention: &Retention, now: SystemTime) -> Vec<PathBuf> {
+        let mut expired = Vec::new();
+        let mut count = 0;
+        let mut size = 0;
+        for entry in self.entries.iter_mut() {
+            if entry.favourite || entry.files.is_empty() {
+                continue;
+            }
+            count += 1;
+            size += entry
+                .files
+                .iter()
+                .filter_map(|path| std::fs::metadata(path).ok())
+                .map(|metadata| metadata.len())
+                .sum::<u64>();
+            let age = now
+                .duration_since(SystemTime::UNIX_EPOCH + Duration::from_secs(entry.set_at))
+                .unwrap_or_default();
+            if !retention.keeps(count, size, age) {
+                expired.append(&mut entry.files);
+            }
+        }
+        // The same evolution may have been set again.
+        expired.retain(|path| !self.has_file(path));
+        // Only files the app wrote are removed, whatever the history says.
+        expired.retain(|path| self.manages(path));
+        expired.sort();
+        expired.dedup();
+        expired
+    }
+
+    /// Toggles the favourite flag of the current wallpaper and returns it.
+    pub fn toggle_favourite_latest(&mut self) -> Option<&HistoryEntry> {
+        let entry = self.entries.front_mut()?;
+        entry.favourite = !entry.favourite;
+        Some(entry)
+    }
+
+    /// Whether a wallpaper still has the file.
+    pub fn has_file(&self, path: &Path) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.files.iter().any(|file| file == path))
+    }
+
+    /// Whether the file is in the app folder.
+    fn manages(&self, path: &Path) -> bool {
+        let app_folder_path = match self.path.parent() {
+            Some(app_folder_path) => app_folder_path,
+            None => return false,
+        };
+        path.starts_with(app_folder_path)
+            && !path
+                .components()
+                .any(|component| component == Component::ParentDir)
+    }
+
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter().take(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::TempFolder;
+
+    const DAY: u64 = 24 * 60 * 60;
+    const NOW: u64 = 1_700_000_000;
+
+    // An app folder with a wallpaper of `size` bytes for each `(name, size, days ago)`, newest first.
+    fn history(wallpapers: &[(&str, usize, u64)]) -> (TempFolder, History) {
+        let app_folder_path = TempFolder::new("history");
+        let mut history = History::load(&app_folder_path);
+        for (name, size, days_ago) in wallpapers.iter().rev() {
+            let path = app_folder_path.join(name);
+            std::fs::write(&path, vec![0; *size]).unwrap();
+            history.push(entry(&path, NOW - days_ago * DAY));
+        }
+        (app_folder_path, history)
+    }
+
+    fn entry(path: &Path, set_at: u64) -> HistoryEntry {
+        HistoryEntry {
+            pollen_uuid: "uuid".to_owned(),
+            cid: "cid".to_owned(),
+            evolution: "evolution.jpg".to_owned(),
+            path: path.to_owned(),
+            prompt: None,
+            model: None,
+            set_at,
+            favourite: false,
+            files: vec![path.to_owned()],
+        }
+    }
+
+    fn expire(history: &mut History, retention: Retention) -> Vec<String> {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(NOW);
+        history
+            .expire(&retention, now)
+            .iter()
+            .filter_map(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn retention() -> Retention {
+        Retention {
+            keep_last: None,
+            max_size: None,
+            max_age: None,
+        }
+    }
+
+    #[test]
+    fn keeps_last_wallpapers() {
+        let (_folder, mut history) = history(&[("c", 1, 0), ("b", 1, 1), ("a", 1, 2)]);
+        let retention = Retention {
+            keep_last: Some(2),
+            ..retention()
+        };
+        assert_eq!(expire(&mut history, retention.clone()), ["a"]);
+        // Expired files are forgotten.
+        assert!(expire(&mut history, retention).is_empty());
+    }
+
+    #[test]
+    fn keeps_wallpapers_within_size() {
+        let (_folder, mut history) = history(&[("c", 10, 0), ("b", 10, 1), ("a", 10, 2)]);
+        let retention = Retention {
+            max_size: Some(25),
+            ..retention()
+        };
+        assert_eq!(expire(&mut history, retention), ["a"]);
+    }
+
+    #[test]
+    fn keeps_wallpapers_within_age() {
+        let (_folder, mut history) = history(&[("c", 1, 0), ("b", 1, 3), ("a", 1, 10)]);
+        let retention = Retention {
+            max_age: Some(Duration::from_secs(7 * DAY)),
+            ..retention()
+        };
+        assert_eq!(expire(&mut history, retention), ["a"]);
+    }
+
+    #[test]
+    fn keeps_newest_wallpaper_and_favourites() {
+        let (folder, mut history) = history(&[("c", 10, 30), ("b", 10, 31), ("a", 10, 32)]);
+        history.entries[2].favourite = true;
+        let retention = Retention {
+            keep_last: Some(1),
+            max_size: Some(1),
+            max_age: Some(Duration::from_secs(DAY)),
+        };
+        // Even the newest is over the size and the age.
+        assert_eq!(expire(&mut history, retention), ["b"]);
+        assert!(history.has_file(&folder.join("a")));
+        assert!(history.has_file(&folder.join("c")));
+    }
+
+    #[test]
+    fn never_expires_files_outside_app_folder() {
+        let (folder, mut history) = history(&[("b", 1, 0), ("a", 1, 1)]);
+        let outside = TempFolder::new("outside");
+        history.entries[1].files = vec![
+            folder.join("a"),
+            outside.join("a"),
+            folder.join("..").join("escaped.jpg"),
+        ];
+        let retention = Retention {
+            keep_last: Some(1),
+            ..retention()
+        };
+        assert_eq!(expire(&mut history, retention), ["a"]);
+    }
+}
@@ -0,0 +1,88 @@
+use std::{io::ErrorKind, path::PathBuf, time::Duration};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::{debug, warn};
+
+// Some desktops still read the previous wallpaper for a moment after the new one is set,
+// on Linux the screen turns blue for a split second if it is removed right away.
+#[cfg(target_os = "linux")]
+const REMOVE_DELAY: Duration = Duration::from_millis(1000);
+#[cfg(not(target_os = "linux"))]
+const REMOVE_DELAY: Duration = Duration::ZERO;
+
+/// Removes files in a background task so that the tracker never waits for the file system.
+#[derive(Debug)]
+pub struct Janitor {
+    files: mpsc::UnboundedSender<Vec<PathBuf>>,
+    task: JoinHandle<()>,
+}
+
+impl Janitor {
+    pub fn spawn() -> Self {
+        let (files, mut batches) = mpsc::unbounded_channel::<Vec<PathBuf>>();
+        let task = tokio::spawn(async move {
+            while let Some(batch) = batches.recv().await {
+                if !REMOVE_DELAY.is_zero() {
+                    tokio::time::sleep(REMOVE_DELAY).await;
+                }
+                for path in batch {
+                    match tokio::fs::remove_file(&path).await {
+                        Ok(()) => debug!(path = %path.display(), "removed previous pollen"),
+                        // Removed by hand or by `pollenwall gc`.
+                        Err(err) if err.kind() == ErrorKind::NotFound => {}
+                        Err(err) => {
+                            warn!(path = %path.display(), error = %err, "failed to remove previous pollen")
+                        }
+                    }
+                }
+            }
+        });
+        Self { files, task }
+    }
+
+    /// Removes the files once the ones before them are removed.
+    pub fn remove(&self, paths: Vec<PathBuf>) {
+        if paths.is_empty() {
+            return;
+        }
+        // The task only stops once the janitor is finished.
+        let _ = self.files.send(paths);
+    }
+
+    /// Waits until the files sent so far are removed.
+    pub async fn finish(self) {
+        drop(self.files);
+        if let Err(err) = self.task.await {
+            warn!(error = %err, "janitor stopped");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::TempFolder;
+
+    #[tokio::test]
+    async fn removes_the_files_it_is_given() {
+        let folder = TempFolder::new("janitor");
+        for name in ["previous.jpg", "previous_wallpaper.jpg", "current.jpg"] {
+            std::fs::write(folder.join(name), name).unwrap();
+        }
+
+        let janitor = Janitor::spawn();
+        janitor.remove(vec![
+            folder.join("previous.jpg"),
+            // Already gone.
+            folder.join("missing.jpg"),
+            folder.join("previous_wallpaper.jpg"),
+        ]);
+        janitor.remove(Vec::new());
+        janitor.finish().await;
+
+        let left: Vec<_> = std::fs::read_dir(&*folder)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(left, ["current.jpg"]);
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+pub const LABEL: &str = "com.pollinations.pollenwall";
+pub const PLIST_FILE_NAME: &str = "com.pollinations.pollenwall.plist";
+// Only what is written before logging starts or when it crashes, the logs are rotated in `~/.pollenwall/logs`.
+const ERROR_LOG_FILE_NAME: &str = "errors.log";
+// Seconds launchd waits before starting a service which exited again.
+const THROTTLE_INTERVAL: u64 = 30;
+// Launch agents start with a bare `PATH`, hooks and `ffmpeg` are found with the one of the shell.
+const ENVIRONMENT_VARIABLES: [&str; 1] = ["PATH"];
+
+/// A launchd user agent, serialized as its property list.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LaunchAgent {
+    label: &'static str,
+    program: String,
+    program_arguments: Vec<String>,
+    run_at_load: bool,
+    // Only in the graphical session where the wallpaper can be set.
+    limit_load_to_session_type: &'static str,
+    process_type: &'static str,
+    throttle_interval: u64,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    environment_variables: BTreeMap<String, String>,
+    standard_out_path: String,
+    standard_error_path: String,
+    keep_alive: KeepAlive,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct KeepAlive {
+    successful_exit: bool,
+}
+
+impl LaunchAgent {
+    /// Runs `executable` with `args`, errors outside of the logs go to [`log_folder_path`].
+    pub fn new(
+        executable: &Path,
+        args: &[String],
+        home: &Path,
+        environment_variables: BTreeMap<String, String>,
+    ) -> Self {
+        let program = executable.to_string_lossy().into_owned();
+        let error_log_path = log_folder_path(home)
+            .join(ERROR_LOG_FILE_NAME)
+            .to_string_lossy()
+            .into_owned();
+        Self {
+            label: LABEL,
+            program_arguments: std::iter::once(program.clone())
+                .chain(args.iter().cloned())
+                .collect(),
+            program,
+            run_at_load: true,
+            limit_load_to_session_type: "Aqua",
+            // Background jobs are throttled, post-processing and animations would lag behind.
+            process_type: "Standard",
+            throttle_interval: THROTTLE_INTERVAL,
+            environment_variables,
+            // The terminal output repeats the logs without rotating them.
+            standard_out_path: "/dev/null".to_owned(),
+            standard_error_path: error_log_path,
+            // Restarted unless it quits on its own.
+            keep_alive: KeepAlive {
+                successful_exit: false,
+            },
+        }
+    }
+
+    pub fn to_xml(&self) -> Result<String> {
+        let mut xml = Vec::new();
+        plist::to_writer_xml(&mut xml, self)?;
+        Ok(String::from_utf8(xml)?)
+    }
+}
+
+/// Where macOS keeps the logs of the user's apps.
+pub fn log_folder_path(home: &Path) -> PathBuf {
+    home.join("Library").join("Logs").join("pollenwall")
+}
+
+/// Variables of the current environment which the agent needs.
+pub fn environment() -> BTreeMap<String, String> {
+    ENVIRONMENT_VARIABLES
+        .iter()
+        .filter_map(|name| {
+            std::env::var(name)
+                .ok()
+                .map(|value| (name.to_string(), value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent(args: &[String]) -> LaunchAgent {
+        LaunchAgent::new(
+            Path::new("/usr/local/bin/pollenwall"),
+            args,
+            Path::new("/Users/you"),
+            BTreeMap::from([(
+                "PATH".to_owned(),
+                "/opt/homebrew/bin:/usr/bin:/bin".to_owned(),
+            )]),
+        )
+    }
+
+    #[test]
+    fn generates_plist() {
+        assert_eq!(
+            agent(&["--attach".to_owned()]).to_xml().unwrap(),
+            include_str!("../tests/golden/com.pollinations.pollenwall.plist")
+        );
+    }
+
+    #[test]
+    fn keeps_arguments_intact() {
+        let args = vec![
+            "--attach-to".to_owned(),
+            r#"prompt:<a> & "b" 'c'"#.to_owned(),
+        ];
+        let xml = agent(&args).to_xml().unwrap();
+        let value = plist::Value::from_reader_xml(xml.as_bytes()).unwrap();
+        let program_arguments: Vec<&str> = value
+            .as_dictionary()
+            .and_then(|dict| dict.get("ProgramArguments"))
+            .and_then(plist::Value::as_array)
+            .unwrap()
+            .iter()
+            .filter_map(plist::Value::as_string)
+            .collect();
+        assert_eq!(
+            program_arguments,
+            ["/usr/local/bin/pollenwall", "--attach-to", &args[1]]
+        );
+    }
+
+    #[test]
+    fn leaves_out_empty_environment() {
+        let agent = LaunchAgent::new(
+            Path::new("/usr/local/bin/pollenwall"),
+            &[],
+            Path::new("/Users/you"),
+            BTreeMap::new(),
+        );
+        assert!(!agent.to_xml().unwrap().contains("EnvironmentVariables"));
+    }
+}
@@ -0,0 +1,47 @@
+use crate::{caption::CaptionInfo, postprocess::PostProcessing, shell};
+use anyhow::{bail, Context, Result};
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use tracing::{debug, info};
+
+pub const DEFAULT_LOCK_SCREEN_FILE_NAME: &str = "lockscreen.png";
+
+/// Image for the lock screen which follows the wallpaper.
+#[derive(Debug, Clone)]
+pub struct LockScreen {
+    /// Where the image is written, its extension decides the format.
+    pub path: PathBuf,
+    /// Command which is run after the image is written, `{path}` is replaced with its path.
+    pub command: Option<String>,
+    /// Crop and caption of the lock screen, independent from the wallpaper.
+    pub post_processing: PostProcessing,
+}
+
+impl LockScreen {
+    /// Makes the lock screen image from the pollen which was set as wallpaper.
+    pub async fn update(&self, pollen_path: &Path, caption_info: CaptionInfo) -> Result<()> {
+        self.post_processing
+            .apply_to(pollen_path, &self.path, caption_info)
+            .await?;
+        info!(path = %self.path.display(), "lock screen image updated");
+
+        if let Some(command) = &self.command {
+            let command = command.replace("{path}", &shell::quote(&self.path.to_string_lossy()));
+            debug!(%command, "updating lock screen");
+            let output = shell::command(&command)
+                .stdin(Stdio::null())
+                .output()
+                .await
+                .with_context(|| format!("Couldn't run \"{}\"", command))?;
+            if !output.status.success() {
+                bail!(
+                    "Lock screen command failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+        }
+        Ok(())
+    }
+}
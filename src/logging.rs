@@ -0,0 +1,106 @@
+use anyhow::{bail, Result};
+use std::{path::Path, str::FromStr};
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+pub const LOGS_FOLDER_NAME: &str = "logs";
+const LOG_FILE_PREFIX: &str = "pollenwall";
+const LOG_FILE_SUFFIX: &str = "log";
+// A week of daily log files is kept, older ones are removed by the appender.
+const MAX_LOG_FILES: usize = 7;
+#[cfg(target_os = "linux")]
+const SYSLOG_IDENTIFIER: &str = "pollenwall";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LogFormat {
+    /// Human readable lines.
+    Pretty,
+    /// One json object per line.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!(
+                "Unknown log format \"{}\", expected \"json\" or \"pretty\"",
+                s
+            ),
+        }
+    }
+}
+
+/// Systemd sets `JOURNAL_STREAM` for services whose output is connected to the journal.
+#[cfg(target_os = "linux")]
+fn running_under_systemd() -> bool {
+    std::env::var_os("JOURNAL_STREAM").is_some()
+}
+
+/// Initializes the global subscriber.
+///
+/// Events are written to the terminal (or to journald when running as a systemd service)
+/// and to a daily rotated file in `logs_dir`. With `terminal` off events only go to the file.
+/// The returned guard flushes the file writer when dropped so it should live until the app exits.
+pub fn init(
+    format: LogFormat,
+    level: &str,
+    logs_dir: &Path,
+    terminal: bool,
+) -> Result<WorkerGuard> {
+    // `RUST_LOG` takes precedence over the level given from the command line.
+    let filter = EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(level))?;
+
+    let file_appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(LOG_FILE_PREFIX)
+        .filename_suffix(LOG_FILE_SUFFIX)
+        .max_log_files(MAX_LOG_FILES)
+        .build(logs_dir)?;
+    let (file_writer, guard) = tracing_appender::non_blocking(file_appender);
+
+    let mut layers = Vec::new();
+
+    let file_layer = tracing_subscriber::fmt::layer()
+        .with_writer(file_writer)
+        .with_ansi(false);
+    layers.push(match format {
+        LogFormat::Pretty => file_layer.boxed(),
+        LogFormat::Json => file_layer.json().boxed(),
+    });
+
+    // Journald already records the time and the level, prefer it when available.
+    #[cfg(target_os = "linux")]
+    let journald_layer = if running_under_systemd() {
+        tracing_journald::layer()
+            .ok()
+            .map(|layer| layer.with_syslog_identifier(SYSLOG_IDENTIFIER.into()))
+    } else {
+        None
+    };
+    #[cfg(not(target_os = "linux"))]
+    let journald_layer: Option<tracing_subscriber::layer::Identity> = None;
+
+    if let Some(journald_layer) = journald_layer {
+        layers.push(journald_layer.boxed());
+    } else if terminal {
+        let stdout_layer = tracing_subscriber::fmt::layer();
+        layers.push(match format {
+            LogFormat::Pretty => stdout_layer.boxed(),
+            LogFormat::Json => stdout_layer.json().boxed(),
+        });
+    }
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layers)
+        .try_init()?;
+
+    Ok(guard)
+}
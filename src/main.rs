@@ -1,31 +1,112 @@
+mod animation;
+mod archive;
+mod attach;
+mod backend;
+mod cache;
+mod caption;
+mod config;
+mod dashboard;
+mod evolution;
+mod files;
+mod heartbeat;
+mod history;
+mod janitor;
+// Only tested on other platforms.
+#[cfg(any(target_os = "macos", test))]
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+mod launchd;
+mod lockscreen;
+mod logging;
+mod media;
+mod metrics;
+mod output;
+mod postprocess;
+mod quality;
+mod restore;
+mod retention;
+mod screen;
+mod service;
+mod shell;
+mod signal;
+mod state;
+mod status;
+mod theme;
 mod tui;
 
+use animation::{AnimatedWallpaper, AnimationFormat, AnimationSettings, ANIMATIONS_FOLDER_NAME};
 use anyhow::{anyhow, bail, Result};
+use chrono::Timelike;
 use dirs::home_dir;
 use ipfs_api::{
-    response::{BlockStatResponse, FileLsResponse, IpfsHeader},
+    response::{BlockStatResponse, FileLsResponse, IpfsHeader, PubsubSubResponse},
     IpfsApi, IpfsClient, TryFromUri,
 };
 use multibase::Base;
-use serde::{ser::SerializeStruct, Serialize};
 use std::{
-    collections::HashMap,
+    cell::RefCell,
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::OsString,
     fs::{self},
+    net::SocketAddr,
     path::{Path, PathBuf},
-    process::Command,
-    time::SystemTime,
-    vec,
+    rc::Rc,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{mpsc, watch},
+    task::JoinSet,
 };
-use tokio::io::AsyncWriteExt;
 use tokio_stream::StreamExt;
+use tracing::{debug, error, info, warn};
+use tracing_appender::non_blocking::WorkerGuard;
 
+use attach::AttachSelector;
+use backend::Backend;
+use cache::Cache;
+use caption::{CaptionField, CaptionInfo, CaptionPosition, CaptionStyle};
 use clap::{App, Arg, ArgMatches};
-use crossterm::style::Stylize;
-use tui::{Tui, BEE, BRUSH};
+use dashboard::{Command, DashboardState, ProcessingPollen};
+use evolution::EvolutionStrategy;
+use heartbeat::Heartbeat;
+use history::{History, HistoryEntry};
+use janitor::Janitor;
+use lockscreen::{LockScreen, DEFAULT_LOCK_SCREEN_FILE_NAME};
+use logging::{LogFormat, LOGS_FOLDER_NAME};
+use media::{MediaPolicies, MediaPolicy, MediaType};
+use metrics::Metrics;
+use output::{Output, OutputSource, Outputs, WallpaperSource};
+use postprocess::{Background, Fit, PostProcessing};
+use quality::{Hours, ImageStats, QualityGate};
+use retention::Retention;
+use screen::ScreenSize;
+use signal::{Received, Signals};
+use state::{EvolutionState, PollenState, State};
+use status::{Status, StatusPollen};
+use theme::{Theme, THEME_FOLDER_NAME};
+use tui::Tui;
 const APP_FOLDER_NAME: &str = ".pollenwall";
 const DEFAULT_POLLINATIONS_MULTIADDR: &str = "/ip4/65.108.44.19/tcp/5005";
 const WALLPAPER_SET_DELAY: u64 = 500;
 const HEARTBEAT: &str = "HEARTBEAT";
+const PUBSUB_RECONNECT_DELAY: u64 = 5000;
+const DEFAULT_HEARTBEAT_TIMEOUT: &str = "180";
+// Megabytes
+const DEFAULT_CACHE_SIZE: &str = "500";
+const DEFAULT_ANIMATION_FPS: &str = "12";
+// How often heartbeat staleness is checked and the status file is refreshed.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
+// How long quitting waits for downloads, animations and exports to finish.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+const DASHBOARD_HISTORY_LENGTH: usize = 10;
+// Minutes
+const DEFAULT_POLLEN_TTL: &str = "30";
+// Minutes
+const DEFAULT_ATTACH_TIMEOUT: &str = "10";
+// Done pollens remembered to drop duplicate done messages.
+const DONE_POLLENS_REMEMBERED: usize = 100;
 
 #[derive(Debug, PartialEq, Clone)]
 enum Topic {
@@ -34,22 +115,105 @@ enum Topic {
     Unknown,
 }
 
-#[derive(Debug, PartialEq)]
+impl Topic {
+    fn name(&self) -> &'static str {
+        match self {
+            Topic::ProcessingPollen => "processing_pollen",
+            Topic::DonePollen => "done_pollen",
+            Topic::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 enum PollenStatus {
     Processing,
     Done,
     OnceSetAsWallpaper,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 enum Model {
     WikiArt,
     VitB32,
     GuidedDiffusion,
     Unknown,
 }
+
+impl Model {
+    fn name(&self) -> &'static str {
+        match self {
+            Model::WikiArt => "wiki_art",
+            Model::VitB32 => "vit_b32",
+            Model::GuidedDiffusion => "guided_diffusion",
+            Model::Unknown => "unknown",
+        }
+    }
+}
+
+impl FromStr for Model {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "wiki_art" => Ok(Model::WikiArt),
+            "vit_b32" => Ok(Model::VitB32),
+            "guided_diffusion" => Ok(Model::GuidedDiffusion),
+            "unknown" => Ok(Model::Unknown),
+            _ => bail!(
+                "Unknown model \"{}\", expected one of \"wiki_art\", \"vit_b32\", \"guided_diffusion\" or \"unknown\"",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Subcommand {
+    Status,
+    // Selector to attach with, `None` picks a pollen interactively.
+    Attach(Option<AttachSelector>),
+    // Only lists the files when dry running.
+    Gc { dry_run: bool },
+    Service(service::Action),
+}
+
 #[derive(Debug)]
-struct PollenWallSetup(PathBuf, String, bool, PathBuf);
+struct PollenWallSetup {
+    subcommand: Option<Subcommand>,
+    app_folder_path: PathBuf,
+    multiaddr: String,
+    settings: Settings,
+    restore_on_exit: bool,
+    metrics_addr: Option<SocketAddr>,
+    heartbeat_timeout: Duration,
+    dashboard: bool,
+    home: PathBuf,
+    // Flushes buffered log lines to the log file when dropped.
+    log_guard: WorkerGuard,
+}
+
+/// Options which are read again from the config on `SIGHUP`.
+#[derive(Debug)]
+struct Settings {
+    attach_mode: bool,
+    attach_selector: AttachSelector,
+    archive: bool,
+    animation: Option<AnimationSettings>,
+    media_policies: MediaPolicies,
+    evolution_strategy: EvolutionStrategy,
+    post_processing: Option<PostProcessing>,
+    quality_gate: QualityGate,
+    lock_screen: Option<LockScreen>,
+    theme: Option<Theme>,
+    backend: Backend,
+    outputs: Outputs,
+    retention: Retention,
+    // Bytes
+    cache_size: u64,
+    pollen_ttl: Duration,
+    attach_timeout: Duration,
+}
 
 #[derive(Debug)]
 struct PollenInfo {
@@ -62,7 +226,12 @@ struct PollenInfo {
     text_input: Option<String>,
     hash_of_current_iteration: String,
     last_polled_evolution: Option<PolledEvolutionInfo>,
+    // Ipfs hash of the evolution which was set as wallpaper last.
+    last_set_evolution: Option<String>,
     status: PollenStatus,
+    first_seen: Instant,
+    // Last message about the pollen.
+    last_seen: Instant,
 }
 
 impl Default for PollenInfo {
@@ -74,7 +243,10 @@ impl Default for PollenInfo {
             text_input: None,
             hash_of_current_iteration: String::new(),
             last_polled_evolution: None,
+            last_set_evolution: None,
             status: PollenStatus::Processing,
+            first_seen: Instant::now(),
+            last_seen: Instant::now(),
         }
     }
 }
@@ -93,9 +265,12 @@ impl PollenInfo {
             topic,
             hash_of_current_iteration,
             last_polled_evolution: None,
+            last_set_evolution: None,
             model_type,
             text_input,
             status: PollenStatus::Processing,
+            first_seen: Instant::now(),
+            last_seen: Instant::now(),
         }
     }
 
@@ -112,16 +287,24 @@ impl PollenInfo {
             topic,
             hash_of_current_iteration,
             last_polled_evolution: None,
+            last_set_evolution: None,
             model_type,
             text_input,
             status,
+            first_seen: Instant::now(),
+            last_seen: Instant::now(),
         }
     }
+
+    /// Text input of the pollen without the json quoting.
+    fn prompt(&self) -> Option<String> {
+        self.text_input.as_ref().map(|text_input| {
+            serde_json::from_str::<String>(text_input).unwrap_or_else(|_| text_input.clone())
+        })
+    }
 }
 
 #[derive(Debug, Default)]
-#[allow(dead_code)]
-// Currently not used but maybe used later
 struct PolledEvolutionInfo {
     hash: String,
     name: String,
@@ -132,6 +315,10 @@ impl PolledEvolutionInfo {
     fn new(hash: String, name: String, size: u64) -> Self {
         PolledEvolutionInfo { hash, name, size }
     }
+
+    fn number(&self) -> Option<u64> {
+        evolution::parse(&self.name).map(|evolution| evolution.number)
+    }
 }
 
 impl From<&IpfsHeader> for PolledEvolutionInfo {
@@ -142,15 +329,97 @@ impl From<&IpfsHeader> for PolledEvolutionInfo {
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    // The cursor is shown again when `tui` is dropped.
     let tui = Tui::new();
-    tui.hide_cursor()?;
-    run(setup(&tui)?).await?;
+    let setup = setup(&tui)?;
+    match setup.subcommand {
+        Some(Subcommand::Status) => {
+            Status::read(&setup.app_folder_path)?.print(WATCHDOG_INTERVAL);
+        }
+        Some(Subcommand::Attach(ref selector)) => {
+            let selector = match selector {
+                Some(selector) => Some(selector.clone()),
+                None => attach::choose(&Status::read(&setup.app_folder_path)?)?,
+            };
+            if let Some(selector) = selector {
+                attach::request(&setup.app_folder_path, &selector)?;
+                println!(
+                    "Asked the running \"pollenwall\" to attach with \"{}\".",
+                    selector
+                );
+            }
+        }
+        Some(Subcommand::Gc { dry_run }) => {
+            retention::collect(
+                &setup.app_folder_path,
+                &setup.settings.retention,
+                setup.settings.cache_size,
+                dry_run,
+            )?;
+        }
+        Some(Subcommand::Service(ref action)) => {
+            if !cfg!(target_os = "linux") {
+                bail!("\"pollenwall service\" is only available on Linux, please use \"--generate-service\" instead.");
+            }
+            service::run(action, &setup.home)?;
+        }
+        None => {
+            // Only the daemon draws a spinner, subcommands may prompt.
+            tui.hide_cursor()?;
+            // Futures of the ipfs client aren't `Send`, background tasks using it are spawned on this local set.
+            tokio::task::LocalSet::new().run_until(run(setup)).await?
+        }
+    }
     Ok(())
 }
 
-fn get_command_line_args() -> ArgMatches {
+fn app() -> App<'static> {
     App::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
+        // Options on the command line override the ones from the config.
+        .args_override_self(true)
+        .subcommand(
+            App::new("status").about("Show the status of the running \"pollenwall\"."),
+        )
+        .subcommand(
+            App::new("attach")
+                .about("Switch the attached pollen of the running \"pollenwall\".")
+                .arg(
+                    Arg::new("selector")
+                        .help("\"first\", \"latest\", \"uuid:<uuid>\", \"prompt:<keyword>\" or \"model:<model>\".")
+                        .value_name("selector")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("gc")
+                .about("Remove the wallpapers which the retention options don't keep.")
+                .arg(
+                    Arg::new("dry-run")
+                        .help("List the files instead of removing them.")
+                        .long("dry-run")
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            App::new("service")
+                .about("Run \"pollenwall\" as a systemd user service, only on Linux.")
+                .subcommand_required(true)
+                .subcommand(
+                    App::new("install")
+                        .about("Install, enable and start the service.")
+                        .arg(
+                            Arg::new("args")
+                                .help("Options to run \"pollenwall\" with, after \"--\". The ones in \"~/.pollenwall/config\" are read too.")
+                                .value_name("args")
+                                .multiple_values(true)
+                                .last(true)
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(App::new("uninstall").about("Stop, disable and remove the service."))
+                .subcommand(App::new("status").about("Show the status of the service.")),
+        )
         .arg(
             Arg::new("addr")
                 .help("You may give a custom address to pollinations ipfs node.")
@@ -172,25 +441,393 @@ fn get_command_line_args() -> ArgMatches {
                 .long("clean")
                 .takes_value(false),
         )
+        .arg(
+            Arg::new("keep-last")
+                .help("Keep the files of this many wallpapers, favourites are always kept. Only the current wallpaper is kept when no retention option is given.")
+                .long("keep-last")
+                .value_name("count")
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("keep-size")
+                .help("Keep the files of the latest wallpapers up to this many megabytes together.")
+                .long("keep-size")
+                .value_name("MB")
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("keep-days")
+                .help("Keep the files of wallpapers set within this many days.")
+                .long("keep-days")
+                .value_name("days")
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("cache-size")
+                .help("Megabytes of downloaded evolutions to keep in \"~/.pollenwall/cache\" so that they aren't downloaded again, the least recently used ones are removed first.")
+                .long("cache-size")
+                .value_name("MB")
+                .default_value(DEFAULT_CACHE_SIZE)
+                .global(true)
+                .takes_value(true),
+        )
         .arg(
             Arg::new("attach")
-                .help("Attach to a random processing pollen until its evolution is done.")
+                .help("Attach to a processing pollen until its evolution is done.")
                 .short('a')
                 .long("attach")
                 .takes_value(false),
         )
+        .arg(
+            Arg::new("attach-to")
+                .help("Choose the pollen to attach to, implies \"--attach\". One of \"first\", \"latest\", \"uuid:<uuid>\", \"prompt:<keyword>\" or \"model:<model>\" where model is \"wiki_art\", \"vit_b32\" or \"guided_diffusion\".")
+                .long("attach-to")
+                .value_name("selector")
+                .default_value("first")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("attach-timeout")
+                .help("Attach to another pollen if the attached one isn't heard of for this many minutes.")
+                .long("attach-timeout")
+                .value_name("minutes")
+                .default_value(DEFAULT_ATTACH_TIMEOUT)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("pollen-ttl")
+                .help("Forget processing pollens which aren't heard of for this many minutes.")
+                .long("pollen-ttl")
+                .value_name("minutes")
+                .default_value(DEFAULT_POLLEN_TTL)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("archive")
+                .help("Mirror the inputs and all outputs of done pollens to \"~/.pollenwall/archive\".")
+                .long("archive")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("animate")
+                .help("Assemble the evolutions of done pollens into a timelapse in \"~/.pollenwall/animations\". \"webp\" and \"mp4\" need \"ffmpeg\".")
+                .long("animate")
+                .value_name("format")
+                .possible_values(["gif", "webp", "mp4"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("animation-fps")
+                .help("Frames per second of the timelapse.")
+                .long("animation-fps")
+                .value_name("fps")
+                .default_value(DEFAULT_ANIMATION_FPS)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("animated-wallpaper-command")
+                .help("Command which loops the timelapse as wallpaper, \"{path}\" is replaced with its path. For example \"mpvpaper -o 'loop' '*' {path}\". Implies \"--animate gif\" if no format is given.")
+                .long("animated-wallpaper-command")
+                .value_name("command")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("media-policy")
+                .help("What to do with evolutions of a format, as \"<format>=<policy>\" where format is one of \"jpg\", \"png\", \"webp\", \"gif\", \"mp4\", \"webm\" and policy is one of \"wallpaper\", \"animate\", \"skip\". Images are set as wallpaper, gifs and videos are animated by default. May be given multiple times.")
+                .long("media-policy")
+                .value_name("format=policy")
+                .multiple_occurrences(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("evolution")
+                .help("Which evolutions of the attached pollen are set as wallpaper. One of \"latest\", \"final\" (only the last one when the pollen is done), \"step:<number>\" or \"every:<number>\".")
+                .long("evolution")
+                .value_name("strategy")
+                .default_value("latest")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("upscale")
+                .help("Upscale wallpapers by this factor with lanczos filtering before setting them.")
+                .long("upscale")
+                .value_name("factor")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("upscale-command")
+                .help("Command which upscales wallpapers instead of lanczos filtering, \"{input}\" and \"{output}\" are replaced with the image paths. For example \"realesrgan-ncnn-vulkan -i {input} -o {output}\".")
+                .long("upscale-command")
+                .value_name("command")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("fit")
+                .help("Place wallpapers on the screen. \"fit\" scales to fit inside and fills the rest with the background, \"fill\" scales to cover and crops, \"center\" keeps the size.")
+                .long("fit")
+                .value_name("mode")
+                .possible_values(["none", "fit", "fill", "center"])
+                .default_value("none")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("background")
+                .help("What fills the screen around fitted and centered wallpapers, \"blur\" or a color like \"#1e1e2e\".")
+                .long("background")
+                .value_name("background")
+                .default_value("blur")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("screen-size")
+                .help("Resolution to fit wallpapers to as \"<width>x<height>\", detected when not given.")
+                .long("screen-size")
+                .value_name("size")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("caption")
+                .help("Write details of the pollen on the wallpaper, comma separated \"prompt\", \"model\" and \"date\". Only the prompt when no details are given.")
+                .long("caption")
+                .value_name("details")
+                .default_missing_value("prompt")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("caption-font")
+                .help("Path of a \"ttf\" or \"otf\" font for the caption, a system font is used when not given.")
+                .long("caption-font")
+                .value_name("path")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("caption-position")
+                .help("Where the caption is placed on the wallpaper.")
+                .long("caption-position")
+                .value_name("position")
+                .possible_values(["top-left", "top", "top-right", "bottom-left", "bottom", "bottom-right"])
+                .default_value("bottom-left")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("caption-size")
+                .help("Height of the caption text in pixels, scales with the wallpaper when not given.")
+                .long("caption-size")
+                .value_name("pixels")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("caption-opacity")
+                .help("Opacity of the caption text between 0 and 1.")
+                .long("caption-opacity")
+                .value_name("opacity")
+                .default_value("1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("caption-box-opacity")
+                .help("Opacity of the dark box behind the caption between 0 and 1, 0 leaves it out.")
+                .long("caption-box-opacity")
+                .value_name("opacity")
+                .default_value("0.6")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("reject-blank")
+                .help("Don't set evolutions which are a single flat color as wallpaper.")
+                .long("reject-blank")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("min-brightness")
+                .help("Don't set evolutions darker than this mean brightness between 0 and 1 as wallpaper.")
+                .long("min-brightness")
+                .value_name("brightness")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("max-brightness")
+                .help("Don't set evolutions brighter than this mean brightness between 0 and 1 as wallpaper.")
+                .long("max-brightness")
+                .value_name("brightness")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("min-contrast")
+                .help("Don't set evolutions with less contrast, the standard deviation of brightness between 0 and 0.5, as wallpaper.")
+                .long("min-contrast")
+                .value_name("contrast")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("min-entropy")
+                .help("Don't set evolutions with less detail, the entropy of brightness in bits between 0 and 8, as wallpaper.")
+                .long("min-entropy")
+                .value_name("bits")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("dark-only")
+                .help("Only set dark evolutions as wallpaper during these hours of local time, for example \"20-7\".")
+                .long("dark-only")
+                .value_name("hours")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("dark-threshold")
+                .help("Mean brightness between 0 and 1 below which an evolution is dark for \"--dark-only\".")
+                .long("dark-threshold")
+                .value_name("brightness")
+                .default_value("0.35")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("lock-screen")
+                .help("Also write an image for the lock screen to this path whenever the wallpaper changes, its extension decides the format. For example \"~/.cache/lockscreen.png\" for \"swaylock -i\" or \"i3lock -i\".")
+                .long("lock-screen")
+                .value_name("path")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("lock-screen-command")
+                .help("Command which is run after the lock screen image is written, \"{path}\" is replaced with its path. For example \"betterlockscreen -u {path}\". Implies \"--lock-screen ~/.pollenwall/lockscreen.png\" if no path is given.")
+                .long("lock-screen-command")
+                .value_name("command")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("lock-screen-fit")
+                .help("Place the lock screen image on the screen, like \"--fit\".")
+                .long("lock-screen-fit")
+                .value_name("mode")
+                .possible_values(["none", "fit", "fill", "center"])
+                .default_value("fill")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("lock-screen-caption")
+                .help("Write the caption on the lock screen image too, styled like \"--caption\".")
+                .long("lock-screen-caption")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("theme")
+                .help("Export the palette of each wallpaper as pywal compatible \"colors.json\", \"colors.Xresources\" and \"colors.css\" to \"~/.pollenwall/theme\".")
+                .long("theme")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("theme-hook")
+                .help("Command which is run after the theme is exported, \"{path}\" is replaced with the theme folder. For example \"xrdb -merge {path}/colors.Xresources\". Implies \"--theme\".")
+                .long("theme-hook")
+                .value_name("command")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("wallpaper-command")
+                .help("Command which sets the wallpaper instead of the platform's own way, \"{path}\" is replaced with the wallpaper path and \"{output}\" with the output name or \"*\" for every output. For example \"swww img -o {output} {path}\".")
+                .long("wallpaper-command")
+                .value_name("command")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("output")
+                .help("Give an output its own wallpapers as \"<name>[@<width>x<height>+<x>+<y>][=<source>]\" where source is \"all\", \"attached\" or \"done\". Needs \"--wallpaper-command\", may be given multiple times.")
+                .long("output")
+                .value_name("output")
+                .multiple_occurrences(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("span")
+                .help("Span each wallpaper across the outputs, they are detected with \"xrandr\" when not given. Needs \"--wallpaper-command\".")
+                .long("span")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .help("Log the wallpapers instead of setting them.")
+                .long("dry-run")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("restore-on-exit")
+                .help("Set the wallpaper which was set before \"pollenwall\" started back when it stops.")
+                .long("restore-on-exit")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("dashboard")
+                .help("Show a full screen dashboard of processing pollens and recent wallpapers.")
+                .short('d')
+                .long("dashboard")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("metrics-addr")
+                .help("Serve prometheus metrics on \"http://<metrics-addr>/metrics\", for example \"127.0.0.1:9184\".")
+                .long("metrics-addr")
+                .value_name("metrics-addr")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("heartbeat-timeout")
+                .help("Reconnect to the pollinations ipfs node if no heartbeat arrives within this many seconds.")
+                .long("heartbeat-timeout")
+                .value_name("seconds")
+                .default_value(DEFAULT_HEARTBEAT_TIMEOUT)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("log-format")
+                .help("Format of the log output.")
+                .long("log-format")
+                .value_name("format")
+                .possible_values(["pretty", "json"])
+                .default_value("pretty")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("log-level")
+                .help("Minimum level of the log output. \"RUST_LOG\" environment variable overrides it.")
+                .long("log-level")
+                .value_name("level")
+                .possible_values(["error", "warn", "info", "debug", "trace"])
+                .default_value("info")
+                .takes_value(true),
+        )
         .arg(
             Arg::new("generate-service")
-                .help("Generates a service file depending on the platform and saves it to \"~/.pollenwall\" folder.")
+                .help("Generates a service file depending on the platform and saves it to \"~/.pollenwall\" folder. Takes the options to run with, quoted as in a shell.")
                 .long("generate-service")
                 .default_missing_value("")
                 .takes_value(true),
         )
-        .get_matches()
+}
+
+/// Parses the options from the config followed by the ones on the command line.
+fn get_command_line_args(config_args: Vec<String>) -> clap::Result<ArgMatches> {
+    let mut args = std::env::args_os();
+    let program = args.next().unwrap_or_else(|| env!("CARGO_PKG_NAME").into());
+    app().try_get_matches_from(
+        std::iter::once(program)
+            .chain(config_args.into_iter().map(OsString::from))
+            .chain(args),
+    )
+}
+
+/// Reads the config again for the options which can change while running.
+fn reload_settings(app_folder_path: &Path) -> Result<Settings> {
+    let args = get_command_line_args(config::read(app_folder_path)?)?;
+    settings(&args, app_folder_path)
 }
 
 fn setup(tui: &Tui) -> Result<PollenWallSetup> {
-    let args = get_command_line_args();
+    // Only for the home directory, the config is read from the app folder in it.
+    let args = app().get_matches();
 
     // Try to discover user's home directory
     let home = match home_dir() {
@@ -200,19 +837,59 @@ fn setup(tui: &Tui) -> Result<PollenWallSetup> {
                 PathBuf::from(path)
             } else {
                 tui.clear_lines(1)?;
-                bail!("{} {}", BEE, "\"pollenwall\" couldn't determine the location of your home directory, to help it please run it with \"--home <absolute-path-to-your-home-directory>\"".blue());
+                bail!("\"pollenwall\" couldn't determine the location of your home directory, to help it please run it with \"--home <absolute-path-to-your-home-directory>\"");
             }
         }
     };
 
     let app_folder_path = get_app_folder_path(&home.to_string_lossy());
 
-    if !app_folder_path.exists() {
-        tui.app_folder_not_found()?;
+    let app_folder_created = !app_folder_path.exists();
+    if app_folder_created {
         // Create ~/.pollenwall
         fs::create_dir_all(&app_folder_path)?;
     }
 
+    // Clean ~/.pollenwall folder, except the config
+    // This is done before initializing logging since the log files live in the app folder.
+    let cleaned = args.is_present("clean");
+    if cleaned {
+        for entry in fs::read_dir(&app_folder_path)? {
+            let entry = entry?;
+            if entry.file_name() == config::CONFIG_FILE_NAME {
+                continue;
+            }
+            if entry.file_type()?.is_dir() {
+                fs::remove_dir_all(entry.path())?;
+            } else {
+                fs::remove_file(entry.path())?;
+            }
+        }
+    }
+
+    let args =
+        get_command_line_args(config::read(&app_folder_path)?).unwrap_or_else(|err| err.exit());
+
+    // Dashboard takes over the terminal so logs only go to the log file.
+    let dashboard = args.is_present("dashboard") && args.subcommand_name().is_none();
+
+    let logs_folder_path = app_folder_path.join(LOGS_FOLDER_NAME);
+    fs::create_dir_all(&logs_folder_path)?;
+    // Unwraps are safe here because these args have default values.
+    let log_guard = logging::init(
+        args.value_of("log-format").unwrap().parse::<LogFormat>()?,
+        args.value_of("log-level").unwrap(),
+        &logs_folder_path,
+        !dashboard,
+    )?;
+
+    if app_folder_created {
+        info!(path = %app_folder_path.display(), "app folder was not found, created it");
+    }
+    if cleaned {
+        info!(path = %app_folder_path.display(), "cleaned app folder");
+    }
+
     // Set pollinations address
     let mutltiaddr = if let Some(addr) = args.value_of("addr") {
         addr
@@ -220,430 +897,1747 @@ fn setup(tui: &Tui) -> Result<PollenWallSetup> {
         DEFAULT_POLLINATIONS_MULTIADDR
     };
 
-    let attach_mode = args.is_present("attach");
-
-    // Clean ~/.pollenwall folder
-    if args.is_present("clean") {
-        fs::remove_dir_all(&app_folder_path)?;
-        fs::create_dir_all(&app_folder_path)?;
-        println!(
-            "{}{}{}",
-            BRUSH,
-            " Cleaned ~/.pollenwall folder! ".green(),
-            BRUSH,
-        );
-    }
+    let settings = settings(&args, &app_folder_path)?;
+
+    let metrics_addr = args
+        .value_of("metrics-addr")
+        .map(|addr| {
+            addr.parse::<SocketAddr>()
+                .map_err(|err| anyhow!("Invalid metrics address \"{}\": {}", addr, err))
+        })
+        .transpose()?;
+
+    // Unwrap is safe here because this arg has a default value.
+    let heartbeat_timeout = args
+        .value_of("heartbeat-timeout")
+        .unwrap()
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .map_err(|err| anyhow!("Invalid heartbeat timeout: {}", err))?;
+
+    let subcommand = match args.subcommand() {
+        Some(("status", _)) => Some(Subcommand::Status),
+        Some(("attach", attach_args)) => Some(Subcommand::Attach(
+            attach_args
+                .value_of("selector")
+                .map(AttachSelector::from_str)
+                .transpose()?,
+        )),
+        Some(("gc", gc_args)) => Some(Subcommand::Gc {
+            dry_run: gc_args.is_present("dry-run"),
+        }),
+        Some(("service", service_args)) => {
+            Some(Subcommand::Service(match service_args.subcommand() {
+                Some(("install", install_args)) => service::Action::Install(
+                    install_args
+                        .values_of("args")
+                        .map(|values| values.map(str::to_owned).collect())
+                        .unwrap_or_default(),
+                ),
+                Some(("uninstall", _)) => service::Action::Uninstall,
+                // Clap makes sure one of them is given.
+                _ => service::Action::Status,
+            }))
+        }
+        _ => None,
+    };
 
     if args.is_present("generate-service") {
         #[cfg(not(target_os = "linux"))]
         #[cfg(not(target_os = "macos"))]
         {
-            // TODO: Tell user that it is not available
+            warn!("generating a service is not available on this platform");
         }
 
         #[cfg(target_os = "macos")]
         {
-            #[derive(Serialize)]
-            #[serde(rename_all = "PascalCase")]
-            struct LaunchAgentMac {
-                label: &'static str,
-                program: String,
-                // Content
-                program_arguments: Vec<String>,
-                // Details
-                run_at_load: bool,
-                // ?
-                limit_load_to_session_type: &'static str,
-                standard_out_path: String,
-                standard_error_path: String,
-                // PathState ? dict?
-                keep_alive: KeepAliveOptionsMac,
-            }
-            #[derive(Serialize)]
-            #[serde(rename_all = "PascalCase")]
-            struct KeepAliveOptionsMac {
-                successful_exit: bool,
-            }
-
             if let Ok(executable_path) = std::env::current_exe() {
-                let args_string: String = args.value_of("generate-service").unwrap().into();
-
-                let mut args_vec = vec![executable_path.to_string_lossy().into()];
-                args_string.split(' ').for_each(|s| {
-                    if !s.is_empty() {
-                        args_vec.push(s.to_string())
-                    }
-                });
-
-                let service = LaunchAgentMac {
-                    label: "com.pollinations.pollenwall",
-                    program: executable_path.to_string_lossy().into(),
-                    program_arguments: args_vec,
-                    run_at_load: true,
-                    limit_load_to_session_type: "Aqua",
-                    standard_out_path: app_folder_path
-                        .join("com.pollinations.pollenwall.log")
-                        .to_str()
-                        .unwrap()
-                        .to_string(),
-                    standard_error_path: app_folder_path
-                        .join("com.pollinations.pollenwall.log")
-                        .to_str()
-                        .unwrap()
-                        .to_string(),
-                    keep_alive: KeepAliveOptionsMac {
-                        successful_exit: false,
-                    },
-                };
-                plist::to_file_xml(
-                    app_folder_path.join("com.pollinations.pollenwall.plist"),
-                    &service,
-                )?;
-            } else {
-                bail!(
-                    "{}",
-                    "Couldn't get current executable path, please try again.".red()
+                let service_args =
+                    service::parse_args(args.value_of("generate-service").unwrap_or_default())?;
+                let agent = launchd::LaunchAgent::new(
+                    &executable_path,
+                    &service_args,
+                    &home,
+                    launchd::environment(),
                 );
+                // Launchd doesn't create the folder of the log file.
+                std::fs::create_dir_all(launchd::log_folder_path(&home))?;
+                let service_path = app_folder_path.join(launchd::PLIST_FILE_NAME);
+                std::fs::write(&service_path, agent.to_xml()?)?;
+                info!(path = %service_path.display(), "generated launchd service");
+            } else {
+                bail!("Couldn't get current executable path, please try again.");
             }
         }
         #[cfg(target_os = "linux")]
         {
-            fn make_systemd_service(
-                description: &str,
-                after: &str,
-                service_type: &str,
-                start_timeout: usize,
-                restart_case: &str,
-                exec_start: &Path,
-                args: &str,
-                wanted_by: &str,
-            ) -> String {
-                format!("[Unit]\nDescription={}\nAfter={}\n[Service]\nType={}\nExecStartPre=/bin/sleep {}\nRestart={}\nExecStart={} {}\n[Install]\nWantedBy={}\n",
-                description, after, service_type, start_timeout, restart_case, exec_start.to_str().unwrap(), args, wanted_by)
-            }
             if let Ok(executable_path) = std::env::current_exe() {
-                let service = make_systemd_service(
-                    "\"Sets your wallpaper with pollens incoming from pollinations.ai\"",
-                    "network-online.target",
-                    "simple",
-                    30,
-                    "on-failure",
+                let service_args =
+                    service::parse_args(args.value_of("generate-service").unwrap_or_default())?;
+                let service = service::unit(
                     &executable_path,
-                    if let Some(args) = args.value_of("generate-service") {
-                        args
-                    } else {
-                        ""
-                    },
-                    "default.target",
+                    &service_args,
+                    &service::session_environment(),
                 );
-
-                // Generally figure this loading service and decide the user logic out.
-                // Explain user
-                // Set permissions
-                std::fs::write(app_folder_path.join("pollenwall.service"), service)?;
+                let service_path = app_folder_path.join(service::SERVICE_FILE_NAME);
+                std::fs::write(&service_path, service)?;
+                info!(path = %service_path.display(), "generated systemd service");
             } else {
-                bail!(
-                    "{}",
-                    "Couldn't get current executable path, please try again.".red()
-                );
+                bail!("Couldn't get current executable path, please try again.");
             }
         }
     }
 
-    Ok(PollenWallSetup(
+    Ok(PollenWallSetup {
+        subcommand,
         app_folder_path,
-        mutltiaddr.into(),
-        attach_mode,
+        multiaddr: mutltiaddr.into(),
+        settings,
+        restore_on_exit: args.is_present("restore-on-exit"),
+        metrics_addr,
+        heartbeat_timeout,
+        dashboard,
         home,
-    ))
+        log_guard,
+    })
 }
 
-async fn run(setup: PollenWallSetup) -> Result<()> {
-    // Init
-    let PollenWallSetup(app_folder_path, multiaddr, attach_mode, _home) = setup;
-    let client = IpfsClient::from_multiaddr_str(&multiaddr).unwrap();
-    let processing_subscription = client.pubsub_sub("processing_pollen", true);
-    let done_subscription = client.pubsub_sub("done_pollen", true);
-    let mut merged = done_subscription.merge(processing_subscription);
-    let mut pollens = HashMap::<String, PollenInfo>::new();
-    let mut pollen_uuid_to_attach: Option<String> = None;
-
-    println!(
-        "{}{}{}",
-        BEE,
-        " Waiting for new pollens to arrive, keep it running.. zZzZ ".yellow(),
-        BEE,
+/// Options which can change while running, see `Settings`.
+fn settings(args: &ArgMatches, app_folder_path: &Path) -> Result<Settings> {
+    let backend = Backend::new(
+        args.is_present("dry-run"),
+        args.value_of("wallpaper-command"),
     );
+    let outputs = Outputs::new(
+        args.values_of("output")
+            .into_iter()
+            .flatten()
+            .map(Output::from_str)
+            .collect::<Result<_>>()?,
+        args.is_present("span"),
+        &backend,
+    )?;
+
+    // Outputs which show the attached pollen need attach mode.
+    let attach_mode = args.is_present("attach")
+        || args.occurrences_of("attach-to") > 0
+        || outputs.wants(OutputSource::Attached);
+    // Unwrap is safe here because this arg has a default value.
+    let attach_selector = args
+        .value_of("attach-to")
+        .unwrap()
+        .parse::<AttachSelector>()?;
+    // Unwrap is safe here because this arg has a default value.
+    let attach_timeout = args
+        .value_of("attach-timeout")
+        .unwrap()
+        .parse::<u64>()
+        .map_err(|err| anyhow!("Invalid attach timeout: {}", err))
+        .and_then(|minutes| {
+            minutes
+                .checked_mul(60)
+                .map(Duration::from_secs)
+                .ok_or_else(|| anyhow!("Invalid attach timeout: {} minutes is too long", minutes))
+        })?;
+    // Unwrap is safe here because this arg has a default value.
+    let pollen_ttl = args
+        .value_of("pollen-ttl")
+        .unwrap()
+        .parse::<u64>()
+        .map_err(|err| anyhow!("Invalid pollen ttl: {}", err))
+        .and_then(|minutes| {
+            minutes
+                .checked_mul(60)
+                .map(Duration::from_secs)
+                .ok_or_else(|| anyhow!("Invalid pollen ttl: {} minutes is too long", minutes))
+        })?;
+
+    let archive = args.is_present("archive");
+
+    let animated_wallpaper_command = args.value_of("animated-wallpaper-command");
+    let animation = match (args.value_of("animate"), animated_wallpaper_command) {
+        (None, None) => None,
+        (format, command) => Some(AnimationSettings {
+            format: format.map_or(Ok(AnimationFormat::Gif), AnimationFormat::from_str)?,
+            // Unwrap is safe here because this arg has a default value.
+            fps: args
+                .value_of("animation-fps")
+                .unwrap()
+                .parse::<u32>()
+                .ok()
+                .filter(|fps| *fps > 0)
+                .ok_or_else(|| anyhow!("Animation fps should be a positive number"))?,
+            command: command.map(str::to_owned),
+        }),
+    };
 
-    // Listen for `processing_pollen` and `done_pollen` topics
-    while let Some(input) = merged.next().await {
-        match input {
-            Ok(res) => {
-                if let Some(msg) = res.data {
-                    // Decode base64 response
-                    let msg = decode_msg(msg)?;
-                    // Filter `HEARTBEAT` messages in the stream
-                    if !msg.contains(HEARTBEAT) {
-                        let hash = msg;
-
-                        // Path for the current pollen output
-                        let path_to_current_pollen_output = format!("/ipfs/{}/output", &hash);
-
-                        // Unwrap is safe here because there will always be a topic.
-                        let topic = match &*get_current_topic(&res.topic_ids.unwrap()) {
-                            "done_pollen" => Topic::DonePollen,
-                            "processing_pollen" => Topic::ProcessingPollen,
-                            _ => Topic::Unknown,
-                        };
-
-                        // Ignore unknown topics
-                        if let Topic::Unknown = topic {
-                            continue;
-                        }
+    let media_policies = MediaPolicies::new(
+        args.values_of("media-policy").into_iter().flatten(),
+        animated_wallpaper_command.is_some(),
+    )?;
+
+    // Unwrap is safe here because this arg has a default value.
+    let evolution_strategy = args
+        .value_of("evolution")
+        .unwrap()
+        .parse::<EvolutionStrategy>()?;
+
+    let upscale = args
+        .value_of("upscale")
+        .map(|factor| {
+            factor
+                .parse::<u32>()
+                .ok()
+                .filter(|factor| *factor > 0)
+                .ok_or_else(|| anyhow!("Upscale factor should be a positive number"))
+        })
+        .transpose()?;
+    // Unwraps are safe here because these args have default values.
+    let fit = args.value_of("fit").unwrap().parse::<Fit>()?;
+    let lock_screen_fit = args.value_of("lock-screen-fit").unwrap().parse::<Fit>()?;
+    let background = args.value_of("background").unwrap().parse::<Background>()?;
+    let lock_screen_enabled =
+        args.is_present("lock-screen") || args.is_present("lock-screen-command");
+    let screen_size = match args.value_of("screen-size") {
+        Some(size) => Some(size.parse::<ScreenSize>()?),
+        None if fit != Fit::None || (lock_screen_enabled && lock_screen_fit != Fit::None) => {
+            let size = screen::detect();
+            if size.is_none() {
+                warn!("couldn't detect the screen size, give it with \"--screen-size\" to fit wallpapers");
+            }
+            size
+        }
+        None => None,
+    };
+    let caption_style = |fields: &str| -> Result<CaptionStyle> {
+        let parse_opacity = |name| {
+            // Unwrap is safe here because these args have default values.
+            let opacity = args.value_of(name).unwrap();
+            opacity
+                .parse::<f32>()
+                .map_err(|err| anyhow!("Invalid caption opacity \"{}\": {}", opacity, err))
+        };
+        CaptionStyle::new(
+            fields
+                .split(',')
+                .map(|field| field.trim().parse::<CaptionField>())
+                .collect::<Result<_>>()?,
+            args.value_of("caption-font").map(PathBuf::from),
+            // Unwrap is safe here because this arg has a default value.
+            args.value_of("caption-position")
+                .unwrap()
+                .parse::<CaptionPosition>()?,
+            args.value_of("caption-size")
+                .map(|size| {
+                    size.parse::<f32>()
+                        .map_err(|err| anyhow!("Invalid caption size \"{}\": {}", size, err))
+                })
+                .transpose()?,
+            parse_opacity("caption-opacity")?,
+            parse_opacity("caption-box-opacity")?,
+        )
+    };
+    let caption = args.value_of("caption").map(caption_style).transpose()?;
+    let lock_screen = if lock_screen_enabled {
+        Some(LockScreen {
+            path: args.value_of("lock-screen").map_or_else(
+                || app_folder_path.join(DEFAULT_LOCK_SCREEN_FILE_NAME),
+                PathBuf::from,
+            ),
+            command: args.value_of("lock-screen-command").map(str::to_owned),
+            post_processing: PostProcessing {
+                upscale: None,
+                upscale_command: None,
+                fit: lock_screen_fit,
+                background,
+                screen_size,
+                caption: if args.is_present("lock-screen-caption") {
+                    // Only the prompt when the wallpaper has no caption to copy the style from.
+                    Some(
+                        caption
+                            .clone()
+                            .map_or_else(|| caption_style("prompt"), Ok)?,
+                    )
+                } else {
+                    None
+                },
+            },
+        })
+    } else {
+        None
+    };
+    let threshold = |name: &str, max: f32| -> Result<Option<f32>> {
+        args.value_of(name)
+            .map(|value| match value.parse::<f32>() {
+                Ok(threshold) if (0.0..=max).contains(&threshold) => Ok(threshold),
+                _ => bail!(
+                    "Invalid \"--{}\" \"{}\", expected a number between 0 and {}",
+                    name,
+                    value,
+                    max
+                ),
+            })
+            .transpose()
+    };
+    let quality_gate = QualityGate {
+        reject_blank: args.is_present("reject-blank"),
+        min_brightness: threshold("min-brightness", 1.0)?,
+        max_brightness: threshold("max-brightness", 1.0)?,
+        min_contrast: threshold("min-contrast", 0.5)?,
+        min_entropy: threshold("min-entropy", 8.0)?,
+        dark_only: args
+            .value_of("dark-only")
+            .map(Hours::from_str)
+            .transpose()?,
+        // Unwrap is safe here because this arg has a default value.
+        dark_threshold: threshold("dark-threshold", 1.0)?.unwrap(),
+    };
 
-                        // Get pollen uuid
-                        if let Ok(BlockStatResponse {
-                            key: pollen_uuid, ..
-                        }) = client.block_stat(&*format!("{}/input", &hash)).await
-                        {
-                            let text_input =
-                                get_text_input_from_pollen_uuid(&client, &pollen_uuid).await;
-                            let model_type =
-                                get_model_type_from_pollen_uuid(&client, &pollen_uuid).await;
-
-                            if let Some(pollen) = pollens.get_mut(&pollen_uuid) {
-                                // Pollen is being tracked already so update its info
-                                pollen.topic = topic.to_owned();
-                                pollen.hash_of_current_iteration = hash.to_owned();
-                                pollen.model_type = model_type;
-                                pollen.text_input = text_input;
-                                match pollen.status {
-                                    // Ignore pollen if it once set as wallpaper
-                                    // This would help filtering for duplicate done messages.
-                                    PollenStatus::OnceSetAsWallpaper => match topic {
-                                        // Topic::ProcessingPollen => {
-                                        //     // TODO: Additional logic of attaching to a processing pollen may go here.
-                                        //     if matches.is_present("attach") {
-                                        //         if let Some(uuid) = &pollen_uuid_to_attach {
-                                        //             if pollen_uuid != *uuid {
-                                        //                 // Ignore pollens which are not attached.
-                                        //                 // Else even if it has the same uuid set the new evolution state as wallpaper
-                                        //                 continue;
-                                        //             } else {
-                                        //                 // Renew status for keeping it attached
-                                        //                 pollen.status = PollenStatus::Processing;
-                                        //             }
-                                        //         }
-                                        //     }
-                                        // }
-                                        Topic::ProcessingPollen => {
-                                            // TODO: Simplify this
-                                            // Pass
-                                        }
-                                        Topic::DonePollen => {
-                                            // Ignore done pollens which had been already set as wallpaper
-                                            continue;
-                                        }
-                                        _ => {
-                                            unreachable!();
-                                        }
-                                    },
-                                    // Attaching logic for
-                                    _ => {
-                                        pollen.status = match topic {
-                                            Topic::ProcessingPollen => PollenStatus::Processing,
-                                            Topic::DonePollen => PollenStatus::Done,
-                                            _ => unreachable!(),
-                                        }
-                                    }
-                                }
-                            } else {
-                                // Pollen not tracked yet, store it
-                                // Since it is a done pollen tag it.
-                                pollens.insert(
-                                    pollen_uuid.to_owned(),
-                                    PollenInfo::with_status(
-                                        pollen_uuid.to_owned(),
-                                        topic.to_owned(),
-                                        hash.to_owned(),
-                                        model_type,
-                                        text_input,
-                                        match topic {
-                                            Topic::DonePollen => PollenStatus::Done,
-                                            Topic::ProcessingPollen => PollenStatus::Processing,
-                                            _ => unreachable!(),
-                                        },
-                                    ),
-                                );
-                            }
+    let retention = if ["keep-last", "keep-size", "keep-days"]
+        .iter()
+        .any(|name| args.is_present(name))
+    {
+        let limit = |name: &str| -> Result<Option<u64>> {
+            args.value_of(name)
+                .map(|value| {
+                    value
+                        .parse::<u64>()
+                        .map_err(|err| anyhow!("Invalid \"--{}\" \"{}\": {}", name, value, err))
+                })
+                .transpose()
+        };
+        Retention {
+            keep_last: limit("keep-last")?.map(|count| count as usize),
+            max_size: limit("keep-size")?
+                .map(|megabytes| {
+                    megabytes.checked_mul(1_000_000).ok_or_else(|| {
+                        anyhow!("Invalid \"--keep-size\": {} MB is too large", megabytes)
+                    })
+                })
+                .transpose()?,
+            max_age: limit("keep-days")?
+                .map(|days| {
+                    days.checked_mul(24 * 60 * 60)
+                        .map(Duration::from_secs)
+                        .ok_or_else(|| {
+                            anyhow!("Invalid \"--keep-days\": {} days is too long", days)
+                        })
+                })
+                .transpose()?,
+        }
+    } else {
+        Retention::default()
+    };
 
-                            // Find the latest evolution (image) of pollen
-                            if let Ok(list_of_output_folder) =
-                                client.file_ls(&path_to_current_pollen_output).await
-                            {
-                                if let Some(pollen_header) =
-                                    get_the_latest_image_according_to_numbering(
-                                        &list_of_output_folder,
-                                    )
-                                {
-                                    let processing_pollens_count = pollens
-                                        .values()
-                                        .filter(|pollen| pollen.status == PollenStatus::Processing)
-                                        .count();
-                                    // We know that we have registered that pollen here so we can unwrap
-                                    let pollen = pollens.get_mut(&pollen_uuid).unwrap();
-                                    match pollen.status {
-                                        PollenStatus::Processing => {
-                                            if attach_mode {
-                                                // println!("{:?}", pollen.model_type);
-                                                // println!("{:?}", pollen.text_input);
-
-                                                // Attach to a random processing pollen
-                                                if pollen_uuid_to_attach.is_none() {
-                                                    pollen_uuid_to_attach =
-                                                        Some(pollen_uuid.to_owned());
-                                                }
-                                                // A processing pollen is picked here naturally
-                                                if let Some(uuid) = &pollen_uuid_to_attach {
-                                                    if pollen_uuid == *uuid {
-                                                        // New iteration arrived
-                                                        println!("\n{}", "New generation of attached pollen is arrived!".green());
-                                                        // Save pollen
-                                                        let mut save_path = app_folder_path.clone();
-                                                        save_path.push(&format!(
-                                                            "{}_{}",
-                                                            &pollen_uuid, &pollen_header.name
-                                                        ));
-                                                        let save_time = save_pollen(
-                                                            &client,
-                                                            &pollen_header.hash,
-                                                            &save_path,
-                                                        )
-                                                        .await?;
-
-                                                        // Set wallpaper
-                                                        set_wallpaper_with_delay(
-                                                            save_path.clone(),
-                                                            pollen_header.hash.to_owned(),
-                                                            processing_pollens_count,
-                                                        );
-
-                                                        // Update pollen info
-                                                        if let Some(PollenInfo {
-                                                            last_polled_evolution,
-                                                            ..
-                                                        }) = pollens.get_mut(&pollen_uuid)
-                                                        {
-                                                            *last_polled_evolution =
-                                                                Some(PolledEvolutionInfo::from(
-                                                                    pollen_header,
-                                                                ));
-                                                        }
-
-                                                        // Keep storage clean
-                                                        if let Some(save_time) = save_time {
-                                                            clear_previous_pollens(
-                                                                &app_folder_path,
-                                                                &save_time,
-                                                            )
-                                                            .await?;
-                                                        }
-                                                    } else {
-                                                        // Ignore pollens which are not attached.
-                                                        continue;
-                                                    }
-                                                } else {
-                                                    // No pollen id to attach..
-                                                    // This might be unreachable
-                                                    continue;
-                                                }
-                                            }
-                                        }
-                                        PollenStatus::Done => {
-                                            if attach_mode {
-                                                if let Some(uuid) = &pollen_uuid_to_attach {
-                                                    if pollen_uuid == *uuid {
-                                                        // Attached pollen is done
-                                                        // Empty the slot for a new one to attach
-                                                        pollen_uuid_to_attach = None;
-                                                    } else {
-                                                        // Block other done pollens.
-                                                        continue;
-                                                    }
-                                                }
-                                            }
+    // Unwrap is safe here because this arg has a default value.
+    let cache_size = args
+        .value_of("cache-size")
+        .unwrap()
+        .parse::<u64>()
+        .map_err(|err| anyhow!("Invalid cache size: {}", err))
+        .and_then(|megabytes| {
+            megabytes
+                .checked_mul(1_000_000)
+                .ok_or_else(|| anyhow!("Invalid cache size: {} MB is too large", megabytes))
+        })?;
+
+    let theme = if args.is_present("theme") || args.is_present("theme-hook") {
+        Some(Theme {
+            folder_path: app_folder_path.join(THEME_FOLDER_NAME),
+            hook: args.value_of("theme-hook").map(str::to_owned),
+        })
+    } else {
+        None
+    };
+    let post_processing = Some(PostProcessing {
+        upscale,
+        upscale_command: args.value_of("upscale-command").map(str::to_owned),
+        fit,
+        background,
+        screen_size,
+        caption,
+    })
+    .filter(PostProcessing::is_enabled);
+
+    Ok(Settings {
+        attach_mode,
+        attach_selector,
+        archive,
+        animation,
+        media_policies,
+        evolution_strategy,
+        post_processing,
+        quality_gate,
+        lock_screen,
+        theme,
+        backend,
+        outputs,
+        retention,
+        cache_size,
+        pollen_ttl,
+        attach_timeout,
+    })
+}
 
-                                            println!("\n{}", "Pollen arrived!".green());
-
-                                            // Save pollen
-                                            let mut save_path = app_folder_path.clone();
-                                            save_path.push(&format!(
-                                                "{}_{}",
-                                                &pollen_uuid, &pollen_header.name
-                                            ));
-                                            let save_time = save_pollen(
-                                                &client,
-                                                &pollen_header.hash,
-                                                &save_path,
-                                            )
-                                            .await?;
-
-                                            // Set wallpaper
-                                            set_wallpaper_with_delay(
-                                                save_path.clone(),
-                                                pollen_header.hash.to_owned(),
-                                                processing_pollens_count,
-                                            );
-
-                                            // Update pollen info
-                                            if let Some(PollenInfo {
-                                                status,
-                                                last_polled_evolution,
-                                                ..
-                                            }) = pollens.get_mut(&pollen_uuid)
-                                            {
-                                                *status = PollenStatus::OnceSetAsWallpaper;
-                                                *last_polled_evolution =
-                                                    Some(PolledEvolutionInfo::from(pollen_header));
-                                            }
+async fn run(setup: PollenWallSetup) -> Result<()> {
+    // Init
+    let PollenWallSetup {
+        app_folder_path,
+        multiaddr,
+        settings,
+        restore_on_exit,
+        metrics_addr,
+        heartbeat_timeout,
+        dashboard,
+        log_guard: _log_guard,
+        ..
+    } = setup;
+    let client = IpfsClient::from_multiaddr_str(&multiaddr).unwrap();
+    let metrics = Arc::new(Metrics::new()?);
 
-                                            // Keep storage clean
-                                            if let Some(save_time) = save_time {
-                                                clear_previous_pollens(
-                                                    &app_folder_path,
-                                                    &save_time,
-                                                )
-                                                .await?;
-                                            }
+    if let Some(addr) = metrics_addr {
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(err) = metrics::serve(addr, metrics).await {
+                error!(%addr, error = %err, "metrics endpoint stopped");
+            }
+        });
+    }
+
+    let (wallpaper_events_tx, mut wallpaper_events) = mpsc::unbounded_channel();
+    let (commands_tx, mut commands) = mpsc::unbounded_channel();
+    let mut signals = Signals::new()?;
+
+    let previous_wallpaper = if restore_on_exit {
+        match restore::record(&app_folder_path, &settings.backend) {
+            Ok(Some(path)) => {
+                info!(path = %path.display(), "wallpaper will be restored on exit");
+                Some(path)
+            }
+            Ok(None) => {
+                warn!("couldn't tell the current wallpaper, it won't be restored on exit");
+                None
+            }
+            Err(err) => {
+                warn!(error = %err, "couldn't tell the current wallpaper, it won't be restored on exit");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut tracker = PollenTracker::new(
+        client.clone(),
+        app_folder_path,
+        settings.attach_mode,
+        settings.attach_selector.clone(),
+        metrics,
+        Heartbeat::new(heartbeat_timeout),
+        wallpaper_events_tx,
+    );
+    tracker.apply_settings(settings);
+    let state = State::load(&tracker.app_folder_path);
+    tracker.restore_state(state, SystemTime::now(), Instant::now());
+
+    let dashboard_task = if dashboard {
+        let (state_tx, state_rx) = watch::channel(tracker.dashboard_state());
+        tracker.dashboard = Some(state_tx);
+        let commands_tx = commands_tx.clone();
+        Some(tokio::spawn(async move {
+            if let Err(err) = dashboard::run(state_rx, commands_tx.clone()).await {
+                error!(error = %err, "dashboard stopped");
+            }
+            // Quit when the dashboard is gone, there is no other way to control the app.
+            let _ = commands_tx.send(Command::Quit);
+        }))
+    } else {
+        None
+    };
+
+    let mut watchdog = tokio::time::interval(WATCHDOG_INTERVAL);
+    heartbeat::spawn_watchdog(tracker.heartbeat.liveness());
+    heartbeat::notify_ready();
+
+    info!(%multiaddr, attach_mode = tracker.attach_mode, attach_selector = %tracker.attach_selector, "waiting for new pollens to arrive");
+
+    let result = 'running: loop {
+        let processing_subscription = client.pubsub_sub("processing_pollen", true);
+        let done_subscription = client.pubsub_sub("done_pollen", true);
+        let mut merged = done_subscription.merge(processing_subscription);
+        tracker.heartbeat.subscribed(Instant::now());
+        tracker.connected = true;
+        tracker.publish_state();
+
+        // Listen for `processing_pollen` and `done_pollen` topics
+        loop {
+            tokio::select! {
+                input = merged.next() => match input {
+                    Some(Ok(res)) => {
+                        if let Err(err) = tracker.handle_message(res).await {
+                            break 'running Err(err);
+                        }
+                    }
+                    Some(Err(err)) => {
+                        // Pubsub error
+                        error!(error = ?err, "pubsub error");
+                        continue;
+                    }
+                    None => {
+                        // The stream ends when the connection to the node is lost, subscribe again.
+                        warn!("pubsub subscription ended, reconnecting");
+                        break;
+                    }
+                },
+                _ = watchdog.tick() => {
+                    tracker.watchdog_tick();
+                    if tracker.heartbeat.is_stale(Instant::now()) {
+                        warn!(
+                            timeout = tracker.heartbeat.timeout().as_secs(),
+                            "no heartbeat arrived in time, reconnecting"
+                        );
+                        break;
+                    }
+                }
+                Some(event) = wallpaper_events.recv() => tracker.wallpaper_set(event),
+                Some(command) = commands.recv() => {
+                    if command == Command::Quit {
+                        info!("quitting");
+                        break 'running Ok(());
+                    }
+                    tracker.handle_command(command);
+                }
+                received = signals.recv() => match received {
+                    Received::Shutdown(signal) => {
+                        info!(signal, "quitting");
+                        break 'running Ok(());
+                    }
+                    Received::Reload => tracker.reload(),
+                },
+            }
+        }
+
+        tracker.connected = false;
+        tracker.publish_state();
+
+        let reconnect_delay =
+            tokio::time::sleep(tokio::time::Duration::from_millis(PUBSUB_RECONNECT_DELAY));
+        tokio::pin!(reconnect_delay);
+        loop {
+            tokio::select! {
+                _ = &mut reconnect_delay => break,
+                received = signals.recv() => match received {
+                    Received::Shutdown(signal) => {
+                        info!(signal, "quitting");
+                        break 'running Ok(());
+                    }
+                    Received::Reload => tracker.reload(),
+                },
+            }
+        }
+        tracker.metrics.pubsub_reconnects.inc();
+    };
+
+    if let Some(dashboard_task) = dashboard_task {
+        // The dashboard stops once the state is gone and gives the terminal back.
+        tracker.dashboard = None;
+        if let Err(err) = dashboard_task.await {
+            warn!(error = %err, "dashboard stopped");
+        }
+    }
+    tracker.finish_background_tasks(&mut wallpaper_events).await;
+    if let Some(previous_wallpaper) = previous_wallpaper {
+        // The player would cover the restored wallpaper.
+        tracker.animated_wallpaper.borrow_mut().stop();
+        restore::restore(
+            &tracker.app_folder_path,
+            &tracker.backend,
+            &previous_wallpaper,
+        )
+        .await;
+    }
+    if let Err(err) = tracker.history.save() {
+        warn!(error = %err, "failed to save history");
+    }
+    tracker.save_state();
+    if let Err(err) = tracker.cache.save() {
+        warn!(error = %err, "failed to save cache index");
+    }
+    // Files which no wallpaper keeps anymore are still removed.
+    tracker.janitor.finish().await;
+    result
+}
+
+/// Keeps track of the pollens which arrive from the pubsub topics
+/// and sets the wallpaper when it is their turn.
+struct PollenTracker {
+    client: IpfsClient,
+    app_folder_path: PathBuf,
+    attach_mode: bool,
+    attach_selector: AttachSelector,
+    pollens: HashMap<String, PollenInfo>,
+    pollen_uuid_to_attach: Option<String>,
+    metrics: Arc<Metrics>,
+    heartbeat: Heartbeat,
+    history: History,
+    // Pollens which the user skipped, they won't be attached again.
+    skipped: HashSet<String>,
+    // Done pollens which were set as wallpaper, newest first.
+    done: VecDeque<String>,
+    // Mirror done pollens to the archive folder.
+    archive: bool,
+    animation: Option<AnimationSettings>,
+    animated_wallpaper: Rc<RefCell<AnimatedWallpaper>>,
+    media_policies: MediaPolicies,
+    evolution_strategy: EvolutionStrategy,
+    // Applied to still images before they are set as wallpaper.
+    post_processing: Option<PostProcessing>,
+    // Still images which don't pass are not set.
+    quality_gate: QualityGate,
+    // Follows the wallpaper when it is set.
+    lock_screen: Option<Arc<LockScreen>>,
+    // Exports the palette of the wallpaper when it is set.
+    theme: Option<Arc<Theme>>,
+    backend: Arc<Backend>,
+    outputs: Outputs,
+    // Which files of previous wallpapers are kept.
+    retention: Retention,
+    // Downloaded evolutions.
+    cache: Cache,
+    // Pollens which aren't heard of for this long are forgotten.
+    pollen_ttl: Duration,
+    // The attached pollen is let go if it isn't heard of for this long.
+    attach_timeout: Duration,
+    janitor: Janitor,
+    // Setting wallpapers, archiving, animating and exporting, waited for before quitting.
+    background_tasks: JoinSet<()>,
+    paused: bool,
+    connected: bool,
+    wallpaper_events: mpsc::UnboundedSender<WallpaperSet>,
+    dashboard: Option<watch::Sender<DashboardState>>,
+}
+
+/// Result of setting a wallpaper, sent back to the tracker from the task which sets it.
+#[derive(Debug)]
+struct WallpaperSet {
+    entry: HistoryEntry,
+    // The downloaded pollen before post processing.
+    pollen_path: PathBuf,
+    applied: bool,
+}
+
+impl PollenTracker {
+    fn new(
+        client: IpfsClient,
+        app_folder_path: PathBuf,
+        attach_mode: bool,
+        attach_selector: AttachSelector,
+        metrics: Arc<Metrics>,
+        heartbeat: Heartbeat,
+        wallpaper_events: mpsc::UnboundedSender<WallpaperSet>,
+    ) -> Self {
+        let history = History::load(&app_folder_path);
+        let cache = Cache::load(&app_folder_path, u64::MAX);
+        Self {
+            client,
+            app_folder_path,
+            attach_mode,
+            attach_selector,
+            pollens: HashMap::new(),
+            pollen_uuid_to_attach: None,
+            metrics,
+            heartbeat,
+            history,
+            skipped: HashSet::new(),
+            done: VecDeque::new(),
+            archive: false,
+            animation: None,
+            animated_wallpaper: Rc::default(),
+            media_policies: MediaPolicies::new(std::iter::empty(), false)
+                .expect("default media policies are valid"),
+            evolution_strategy: EvolutionStrategy::default(),
+            post_processing: None,
+            quality_gate: QualityGate::default(),
+            lock_screen: None,
+            theme: None,
+            backend: Arc::new(Backend::System),
+            outputs: Outputs::default(),
+            retention: Retention::default(),
+            cache,
+            // Until the settings are applied.
+            pollen_ttl: Duration::MAX,
+            attach_timeout: Duration::MAX,
+            janitor: Janitor::spawn(),
+            background_tasks: JoinSet::new(),
+            paused: false,
+            connected: false,
+            wallpaper_events,
+            dashboard: None,
+        }
+    }
+
+    /// Takes the pollens back from a previous run, `now` and `now_instant` being the current time.
+    ///
+    /// Pollens which weren't heard of within the ttl are left out, so the settings are applied first.
+    fn restore_state(&mut self, state: State, now: SystemTime, now_instant: Instant) {
+        let pollen_ttl = self.pollen_ttl.as_secs();
+        for pollen in state.pollens {
+            if status::unix_secs(now).saturating_sub(pollen.last_seen) > pollen_ttl {
+                debug!(pollen = %pollen.uuid, "forgot stale pollen of previous run");
+                continue;
+            }
+            self.pollens.insert(
+                pollen.uuid.clone(),
+                PollenInfo {
+                    id: pollen.uuid,
+                    topic: Topic::ProcessingPollen,
+                    model_type: pollen.model.and_then(|model| model.parse().ok()),
+                    text_input: pollen.text_input,
+                    hash_of_current_iteration: pollen.hash,
+                    last_polled_evolution: pollen.evolution.map(|evolution| {
+                        PolledEvolutionInfo::new(evolution.hash, evolution.name, evolution.size)
+                    }),
+                    last_set_evolution: pollen.last_set_evolution,
+                    status: PollenStatus::Processing,
+                    first_seen: instant_at(now, now_instant, pollen.first_seen),
+                    last_seen: instant_at(now, now_instant, pollen.last_seen),
+                },
+            );
+        }
+        self.pollen_uuid_to_attach = state
+            .attached_pollen
+            .filter(|uuid| self.pollens.contains_key(uuid));
+        self.skipped = state.skipped.into_iter().collect();
+        self.done = state.done.into();
+        if !self.pollens.is_empty() {
+            info!(
+                pollens = self.pollens.len(),
+                attached_pollen = self.pollen_uuid_to_attach.as_deref().unwrap_or("none"),
+                "restored pollens of previous run"
+            );
+        }
+        self.pick_pollen_to_attach(now_instant);
+        self.publish_state();
+    }
+
+    /// Snapshots the processing pollens to restore them on the next run.
+    fn save_state(&self) {
+        let state = self.state(SystemTime::now(), Instant::now());
+        if let Err(err) = state.write(&self.app_folder_path) {
+            warn!(error = %err, "failed to save state");
+        }
+    }
+
+    /// The processing pollens, `now` and `now_instant` being the current time.
+    fn state(&self, now: SystemTime, now_instant: Instant) -> State {
+        State {
+            saved_at: status::unix_secs(now),
+            pollens: self
+                .pollens
+                .iter()
+                .filter(|(_, pollen)| pollen.status == PollenStatus::Processing)
+                .map(|(uuid, pollen)| PollenState {
+                    uuid: uuid.clone(),
+                    model: pollen
+                        .model_type
+                        .as_ref()
+                        .map(|model| model.name().to_owned()),
+                    text_input: pollen.text_input.clone(),
+                    hash: pollen.hash_of_current_iteration.clone(),
+                    evolution: pollen.last_polled_evolution.as_ref().map(|evolution| {
+                        EvolutionState {
+                            hash: evolution.hash.clone(),
+                            name: evolution.name.clone(),
+                            size: evolution.size,
+                        }
+                    }),
+                    last_set_evolution: pollen.last_set_evolution.clone(),
+                    first_seen: unix_secs_at(now, now_instant, pollen.first_seen),
+                    last_seen: unix_secs_at(now, now_instant, pollen.last_seen),
+                })
+                .collect(),
+            attached_pollen: self.pollen_uuid_to_attach.clone(),
+            skipped: self.skipped.iter().cloned().collect(),
+            done: self.done.iter().cloned().collect(),
+        }
+    }
+
+    /// Takes the options which can change while running.
+    fn apply_settings(&mut self, settings: Settings) {
+        let Settings {
+            attach_mode,
+            attach_selector,
+            archive,
+            animation,
+            media_policies,
+            evolution_strategy,
+            post_processing,
+            quality_gate,
+            lock_screen,
+            theme,
+            backend,
+            outputs,
+            retention,
+            cache_size,
+            pollen_ttl,
+            attach_timeout,
+        } = settings;
+        self.archive = archive;
+        self.animation = animation;
+        self.media_policies = media_policies;
+        self.evolution_strategy = evolution_strategy;
+        self.post_processing = post_processing;
+        self.quality_gate = quality_gate;
+        self.lock_screen = lock_screen.map(Arc::new);
+        self.theme = theme.map(Arc::new);
+        self.backend = Arc::new(backend);
+        self.outputs = outputs;
+        self.retention = retention;
+        self.cache.max_size = cache_size;
+        self.pollen_ttl = pollen_ttl;
+        self.attach_timeout = attach_timeout;
+
+        if attach_mode && attach_selector != self.attach_selector {
+            self.switch_attachment(attach_selector);
+        } else if attach_mode && !self.attach_mode {
+            info!(selector = %attach_selector, "attach mode turned on");
+            self.attach_mode = true;
+            self.pick_pollen_to_attach(Instant::now());
+        } else if !attach_mode {
+            self.attach_mode = false;
+            self.pollen_uuid_to_attach = None;
+        }
+        self.expire_pollens(Instant::now());
+        self.publish_state();
+    }
+
+    /// Forgets pollens which weren't heard of within the ttl by `now` and lets a silent attached pollen go.
+    fn expire_pollens(&mut self, now: Instant) {
+        let pollen_ttl = self.pollen_ttl;
+        let count = self.pollens.len();
+        self.pollens.retain(|uuid, pollen| {
+            let alive = now.saturating_duration_since(pollen.last_seen) <= pollen_ttl;
+            if !alive {
+                debug!(pollen = %uuid, "forgot stale pollen");
+            }
+            alive
+        });
+        let expired = count != self.pollens.len();
+        if expired {
+            // A pollen which is heard of again is new to the user.
+            let pollens = &self.pollens;
+            self.skipped.retain(|uuid| pollens.contains_key(uuid));
+            self.metrics
+                .processing_pollens
+                .set(self.processing_pollens_count() as i64);
+        }
 
-                                            // Remove from internal store with its uuid.
-                                            pollens.remove_entry(&pollen_uuid);
-                                        }
-                                        _ => unreachable!(),
+        let attached_is_silent = self
+            .pollen_uuid_to_attach
+            .as_ref()
+            .is_some_and(|uuid| !self.is_heard_of(uuid, now));
+        if attached_is_silent {
+            // Unwrap is safe here because the attached pollen is checked above.
+            let pollen_uuid = self.pollen_uuid_to_attach.take().unwrap();
+            info!(pollen = %pollen_uuid, "attached pollen went silent, detaching");
+            self.pick_pollen_to_attach(now);
+        }
+        if expired || attached_is_silent {
+            self.publish_state();
+        }
+    }
+
+    /// Whether the pollen is tracked and was heard of within the attach timeout by `now`.
+    fn is_heard_of(&self, pollen_uuid: &str, now: Instant) -> bool {
+        self.pollens.get(pollen_uuid).is_some_and(|pollen| {
+            now.saturating_duration_since(pollen.last_seen) <= self.attach_timeout
+        })
+    }
+
+    /// Waits for the background tasks and takes in the wallpapers they set before quitting.
+    ///
+    /// Tasks which are still running after the shutdown timeout are stopped.
+    async fn finish_background_tasks(
+        &mut self,
+        wallpaper_events: &mut mpsc::UnboundedReceiver<WallpaperSet>,
+    ) {
+        let deadline = tokio::time::Instant::now() + SHUTDOWN_TIMEOUT;
+        loop {
+            // Setting a wallpaper may start exporting it.
+            while let Ok(event) = wallpaper_events.try_recv() {
+                self.wallpaper_set(event);
+            }
+            match tokio::time::timeout_at(deadline, self.background_tasks.join_next()).await {
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                Err(_) => {
+                    warn!(
+                        tasks = self.background_tasks.len(),
+                        "background tasks didn't finish in time, stopping them"
+                    );
+                    self.background_tasks.shutdown().await;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Reads the config again, the subscription and the pollens are kept.
+    fn reload(&mut self) {
+        match reload_settings(&self.app_folder_path) {
+            Ok(settings) => {
+                self.apply_settings(settings);
+                info!("reloaded config");
+            }
+            Err(err) => warn!(error = %err, "failed to reload config, keeping the current one"),
+        }
+    }
+
+    fn dashboard_state(&self) -> DashboardState {
+        let mut processing: Vec<(&PollenInfo, ProcessingPollen)> = self
+            .pollens
+            .iter()
+            .filter(|(_, pollen)| pollen.status == PollenStatus::Processing)
+            .map(|(uuid, pollen)| {
+                (
+                    pollen,
+                    ProcessingPollen {
+                        uuid: uuid.clone(),
+                        prompt: pollen.prompt(),
+                        model: pollen.model_type.as_ref().map(Model::name),
+                        evolution: pollen
+                            .last_polled_evolution
+                            .as_ref()
+                            .and_then(PolledEvolutionInfo::number),
+                    },
+                )
+            })
+            .collect();
+        // Oldest first so that rows don't jump around.
+        processing.sort_by_key(|(pollen, _)| pollen.first_seen);
+
+        DashboardState {
+            connected: self.connected,
+            last_heartbeat_at: self.heartbeat.last_received_at(),
+            attach_mode: self.attach_mode,
+            attach_selector: self.attach_selector.clone(),
+            paused: self.paused,
+            attached: self.pollen_uuid_to_attach.clone(),
+            processing: processing.into_iter().map(|(_, pollen)| pollen).collect(),
+            history: self
+                .history
+                .recent(DASHBOARD_HISTORY_LENGTH)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn publish_state(&self) {
+        if let Some(dashboard) = &self.dashboard {
+            dashboard.send_replace(self.dashboard_state());
+        }
+    }
+
+    /// Attaches to a processing pollen picked by the selector if none is attached.
+    fn pick_pollen_to_attach(&mut self, now: Instant) {
+        if !self.attach_mode || self.pollen_uuid_to_attach.is_some() {
+            return;
+        }
+        let candidates = self.pollens.iter().filter(|(uuid, pollen)| {
+            // Only pollens with an evolution to show
+            pollen.status == PollenStatus::Processing
+                && pollen.last_polled_evolution.is_some()
+                && !self.skipped.contains(*uuid)
+                && now.saturating_duration_since(pollen.last_seen) <= self.attach_timeout
+        });
+        if let Some(pollen_uuid) = self.attach_selector.select(candidates) {
+            info!(pollen = %pollen_uuid, selector = %self.attach_selector, "attached to pollen");
+            self.pollen_uuid_to_attach = Some(pollen_uuid.clone());
+        }
+    }
+
+    /// Switches the attachment to the pollens chosen by the selector.
+    fn switch_attachment(&mut self, selector: AttachSelector) {
+        info!(%selector, "switching attachment");
+        // Attaching to a pollen implies attach mode.
+        self.attach_mode = true;
+        if let AttachSelector::Uuid(uuid) = &selector {
+            self.skipped
+                .retain(|skipped| !skipped.starts_with(uuid.as_str()));
+        }
+        self.attach_selector = selector;
+        self.pollen_uuid_to_attach = None;
+        self.pick_pollen_to_attach(Instant::now());
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Attach(selector) => self.switch_attachment(selector),
+            Command::Skip => {
+                if let Some(pollen_uuid) = self.pollen_uuid_to_attach.take() {
+                    info!(pollen = %pollen_uuid, "skipped attached pollen");
+                    self.skipped.insert(pollen_uuid);
+                    self.pick_pollen_to_attach(Instant::now());
+                }
+            }
+            Command::Favourite => {
+                if let Some(entry) = self.history.toggle_favourite_latest() {
+                    info!(pollen = %entry.pollen_uuid, favourite = entry.favourite, "toggled favourite");
+                }
+                if let Err(err) = self.history.save() {
+                    warn!(error = %err, "failed to save history");
+                }
+            }
+            Command::Pause => {
+                self.paused = !self.paused;
+                info!(paused = self.paused, "toggled pause");
+            }
+            Command::Quit => {}
+        }
+        self.publish_state();
+    }
+
+    fn wallpaper_set(&mut self, event: WallpaperSet) {
+        if event.applied {
+            self.update_lock_screen(&event);
+            self.update_theme(&event);
+            self.history.push(event.entry);
+            // Previous wallpapers are removed only once the new one is shown.
+            let mut expired = self.history.expire(&self.retention, SystemTime::now());
+            if let Err(err) = self.history.save() {
+                warn!(error = %err, "failed to save history");
+            }
+            // Cached evolutions stay until the cache evicts them.
+            expired.retain(|path| !self.cache.contains(path));
+            self.janitor.remove(expired);
+            self.evict_cache();
+            self.publish_state();
+        } else {
+            // Nothing shows a wallpaper which couldn't be set.
+            let unused = event
+                .entry
+                .files
+                .into_iter()
+                .filter(|path| !self.history.has_file(path) && !self.cache.contains(path))
+                .collect();
+            self.janitor.remove(unused);
+        }
+    }
+
+    /// Removes the least recently used evolutions which no wallpaper keeps.
+    fn evict_cache(&mut self) {
+        let history = &self.history;
+        let evicted = self.cache.evict(|path| history.has_file(path));
+        if evicted.is_empty() {
+            return;
+        }
+        if let Err(err) = self.cache.save() {
+            warn!(error = %err, "failed to save cache index");
+        }
+        self.janitor.remove(evicted);
+    }
+
+    /// Makes the lock screen image from the pollen in the background.
+    fn update_lock_screen(&mut self, event: &WallpaperSet) {
+        let lock_screen = match &self.lock_screen {
+            Some(lock_screen) => lock_screen.clone(),
+            None => return,
+        };
+        // Videos have no image to show.
+        if MediaType::from_name(&event.pollen_path.to_string_lossy())
+            .is_none_or(|media_type| media_type.is_video())
+        {
+            return;
+        }
+        let pollen_path = event.pollen_path.clone();
+        let caption_info = CaptionInfo {
+            prompt: event.entry.prompt.clone(),
+            model: event.entry.model.clone(),
+            date: SystemTime::UNIX_EPOCH + Duration::from_secs(event.entry.set_at),
+        };
+        self.background_tasks.spawn(async move {
+            if let Err(err) = lock_screen.update(&pollen_path, caption_info).await {
+                warn!(path = %lock_screen.path.display(), error = %err, "failed to update lock screen");
+            }
+        });
+    }
+
+    /// Exports the palette of the pollen in the background.
+    fn update_theme(&mut self, event: &WallpaperSet) {
+        let theme = match &self.theme {
+            Some(theme) => theme.clone(),
+            None => return,
+        };
+        // Videos have no image to pick colors from.
+        if MediaType::from_name(&event.pollen_path.to_string_lossy())
+            .is_none_or(|media_type| media_type.is_video())
+        {
+            return;
+        }
+        let pollen_path = event.pollen_path.clone();
+        let wallpaper_path = event.entry.path.clone();
+        self.background_tasks.spawn(async move {
+            if let Err(err) = theme.update(&pollen_path, &wallpaper_path).await {
+                warn!(path = %theme.folder_path.display(), error = %err, "failed to update theme");
+            }
+        });
+    }
+
+    /// Downloads the evolution of the pollen and sets it as wallpaper.
+    ///
+    /// Returns `false` if the evolution couldn't be downloaded, it is tried again with the next message about the pollen.
+    async fn set_as_wallpaper(
+        &mut self,
+        pollen_uuid: &str,
+        pollen_header: &IpfsHeader,
+        source: WallpaperSource,
+        processing_pollens_count: usize,
+    ) -> Result<bool> {
+        if self.paused {
+            debug!(pollen = %pollen_uuid, "paused, not setting wallpaper");
+            return Ok(true);
+        }
+        let outputs = self.outputs.targets(source, self.attach_mode);
+        if outputs.is_empty() {
+            debug!(pollen = %pollen_uuid, ?source, "no output shows the pollen");
+            return Ok(true);
+        }
+
+        let previous_set_evolution = self.pollens.get_mut(pollen_uuid).and_then(|pollen| {
+            pollen
+                .last_set_evolution
+                .replace(pollen_header.hash.clone())
+        });
+
+        // Save pollen
+        let save_path = match save_pollen(
+            &self.client,
+            &pollen_header.hash,
+            &pollen_header.name,
+            &mut self.cache,
+            &self.metrics,
+        )
+        .await
+        {
+            Ok(path) => path,
+            Err(err) => {
+                warn!(
+                    pollen = %pollen_uuid,
+                    evolution = %pollen_header.name,
+                    error = %err,
+                    "failed to download evolution, it is tried again with the next message"
+                );
+                self.metrics.download_failures.inc();
+                if let Some(pollen) = self.pollens.get_mut(pollen_uuid) {
+                    pollen.last_set_evolution = previous_set_evolution;
+                }
+                return Ok(false);
+            }
+        };
+        if let Err(err) = self.cache.save() {
+            warn!(error = %err, "failed to save cache index");
+        }
+
+        let policy = self.media_policies.policy(&pollen_header.name);
+        let is_still_image = MediaType::from_name(&pollen_header.name)
+            .is_some_and(|media_type| media_type.is_still_image());
+
+        // Reject evolutions which don't look good enough
+        let stats = if self.quality_gate.is_enabled() && is_still_image {
+            ImageStats::measure_in_background(save_path.clone()).await
+        } else {
+            None
+        };
+        if let Some(stats) = stats {
+            let hour = chrono::Local::now().hour();
+            if let Some(rejection) = self.quality_gate.check(&stats, hour) {
+                self.metrics
+                    .wallpapers_rejected
+                    .with_label_values(&[rejection.name()])
+                    .inc();
+                info!(
+                    pollen = %pollen_uuid,
+                    evolution = %pollen_header.name,
+                    %rejection,
+                    brightness = stats.brightness,
+                    contrast = stats.contrast,
+                    entropy = stats.entropy,
+                    "evolution rejected"
+                );
+                // It won't pass the next time either.
+                if let Some(path) = self.cache.remove(&pollen_header.hash) {
+                    if !self.history.has_file(&path) {
+                        self.janitor.remove(vec![path]);
+                    }
+                }
+                return Ok(true);
+            }
+        }
+
+        let pollen = self.pollens.get(pollen_uuid);
+        let caption_info = CaptionInfo {
+            prompt: pollen.and_then(PollenInfo::prompt),
+            model: pollen
+                .and_then(|pollen| pollen.model_type.as_ref())
+                .map(|model| model.name().to_owned()),
+            date: SystemTime::now(),
+        };
+
+        // Post process, the saved pollen is kept as it is
+        let pollen_path = save_path.clone();
+        let wallpaper_path = match &self.post_processing {
+            Some(post_processing) if policy == MediaPolicy::Wallpaper && is_still_image => {
+                match post_processing
+                    .apply(&save_path, &self.app_folder_path, caption_info.clone())
+                    .await
+                {
+                    Ok(path) => path,
+                    Err(err) => {
+                        warn!(pollen = %pollen_uuid, error = %err, "failed to post process wallpaper, setting it as it is");
+                        save_path
+                    }
+                }
+            }
+            _ => save_path,
+        };
+
+        // Pick the wallpaper of each output
+        let mut targets: Vec<(Option<String>, PathBuf)> = outputs
+            .iter()
+            .map(|output| (output.name.clone(), wallpaper_path.clone()))
+            .collect();
+        if self.outputs.span() && policy == MediaPolicy::Wallpaper {
+            match output::span(&wallpaper_path, &self.app_folder_path, &outputs).await {
+                Ok(crops) => {
+                    targets = crops
+                        .into_iter()
+                        .map(|(output, path)| (output.name, path))
+                        .collect();
+                }
+                Err(err) => {
+                    warn!(pollen = %pollen_uuid, error = %err, "failed to span wallpaper, setting it on each output")
+                }
+            }
+        }
+
+        // Set wallpaper
+        let mut files = vec![pollen_path.clone(), wallpaper_path.clone()];
+        files.extend(targets.iter().map(|(_, path)| path.clone()));
+        files.sort();
+        files.dedup();
+        let entry = HistoryEntry {
+            pollen_uuid: pollen_uuid.to_owned(),
+            cid: pollen_header.hash.to_owned(),
+            evolution: pollen_header.name.to_owned(),
+            path: wallpaper_path,
+            prompt: caption_info.prompt,
+            model: caption_info.model,
+            set_at: status::unix_secs(caption_info.date),
+            favourite: false,
+            files,
+        };
+        match policy {
+            MediaPolicy::Animate => self.set_as_animated_wallpaper(entry),
+            _ => {
+                self.background_tasks.spawn(set_wallpaper_with_delay(
+                    entry,
+                    pollen_path,
+                    targets,
+                    self.backend.clone(),
+                    processing_pollens_count,
+                    self.metrics.clone(),
+                    self.wallpaper_events.clone(),
+                ));
+            }
+        }
+        Ok(true)
+    }
+
+    /// Loops the evolution with the animated wallpaper command.
+    fn set_as_animated_wallpaper(&mut self, entry: HistoryEntry) {
+        // Policies only animate when there is a command.
+        let command = match self
+            .animation
+            .as_ref()
+            .and_then(|animation| animation.command.as_ref())
+        {
+            Some(command) => command,
+            None => return,
+        };
+        let applied = match self
+            .animated_wallpaper
+            .borrow_mut()
+            .show(command, &entry.path)
+        {
+            Ok(()) => {
+                self.metrics
+                    .wallpaper_sets
+                    .with_label_values(&["success"])
+                    .inc();
+                true
+            }
+            Err(err) => {
+                self.metrics
+                    .wallpaper_sets
+                    .with_label_values(&["failure"])
+                    .inc();
+                error!(path = %entry.path.display(), error = %err, "failed to set animated wallpaper");
+                false
+            }
+        };
+        self.wallpaper_set(WallpaperSet {
+            pollen_path: entry.path.clone(),
+            entry,
+            applied,
+        });
+    }
+
+    /// Mirrors the done pollen in the background.
+    fn archive_pollen(&mut self, pollen_uuid: &str, cid: &str) {
+        let pollen = self.pollens.get(pollen_uuid);
+        let model = pollen
+            .and_then(|pollen| pollen.model_type.as_ref())
+            .map(|model| model.name().to_owned());
+        let prompt = pollen.and_then(PollenInfo::prompt);
+        let client = self.client.clone();
+        let app_folder_path = self.app_folder_path.clone();
+        let pollen_uuid = pollen_uuid.to_owned();
+        let cid = cid.to_owned();
+        self.background_tasks.spawn_local(async move {
+            match archive::archive_pollen(
+                &client,
+                &app_folder_path,
+                &pollen_uuid,
+                &cid,
+                model,
+                prompt,
+            )
+            .await
+            {
+                Ok(Some(manifest)) => info!(
+                    pollen = %pollen_uuid,
+                    files = manifest.files.len(),
+                    path = %archive::pollen_folder_path(&app_folder_path, &pollen_uuid).display(),
+                    "archived pollen"
+                ),
+                Ok(None) => debug!(pollen = %pollen_uuid, "pollen is archived already"),
+                Err(err) => warn!(pollen = %pollen_uuid, error = %err, "failed to archive pollen"),
+            }
+        });
+    }
+
+    /// Assembles the evolutions of the done pollen into a timelapse in the background
+    /// and loops it as wallpaper if there is a command for it.
+    fn animate_pollen(&mut self, pollen_uuid: &str, output: &FileLsResponse) {
+        let settings = match &self.animation {
+            Some(settings) => settings.clone(),
+            None => return,
+        };
+        let mut evolutions: Vec<(u64, String)> = output
+            .objects
+            .values()
+            .flat_map(|object| &object.links)
+            .filter_map(|header| Some((evolution::parse(&header.name)?, header)))
+            // Only still images can be frames.
+            .filter(|(evolution, _)| evolution.media_type.is_still_image())
+            .map(|(evolution, header)| (evolution.number, header.hash.clone()))
+            .collect();
+        evolutions.sort();
+        // One frame per step even if it was saved in several formats.
+        evolutions.dedup_by_key(|(number, _)| *number);
+        if evolutions.len() < 2 {
+            debug!(pollen = %pollen_uuid, "not enough evolutions to animate");
+            return;
+        }
+        let frames: Vec<String> = evolutions.into_iter().map(|(_, hash)| hash).collect();
+
+        let client = self.client.clone();
+        let animations_folder_path = self.app_folder_path.join(ANIMATIONS_FOLDER_NAME);
+        let animated_wallpaper = self.animated_wallpaper.clone();
+        let pollen_uuid = pollen_uuid.to_owned();
+        self.background_tasks.spawn_local(async move {
+            let frames_folder_path = animations_folder_path.join(format!("{}_frames", pollen_uuid));
+            let output_path = animations_folder_path.join(format!(
+                "{}.{}",
+                pollen_uuid,
+                settings.format.extension()
+            ));
+
+            let animated = async {
+                let frames =
+                    animation::download_frames(&client, &frames, &frames_folder_path).await?;
+                animation::encode(frames, settings.format, settings.fps, &output_path).await
+            }
+            .await;
+            if let Err(err) = tokio::fs::remove_dir_all(&frames_folder_path).await {
+                debug!(path = %frames_folder_path.display(), error = %err, "failed to remove frames");
+            }
+            if let Err(err) = animated {
+                warn!(pollen = %pollen_uuid, error = %err, "failed to animate pollen");
+                return;
+            }
+            info!(
+                pollen = %pollen_uuid,
+                frames = frames.len(),
+                path = %output_path.display(),
+                "animated pollen"
+            );
+
+            if let Some(command) = &settings.command {
+                if let Err(err) = animated_wallpaper.borrow_mut().show(command, &output_path) {
+                    warn!(error = %err, "failed to set animated wallpaper");
+                }
+            }
+            animation::clear_previous_animations(&animations_folder_path, &output_path).await;
+        });
+    }
+
+    fn processing_pollens_count(&self) -> usize {
+        self.pollens
+            .values()
+            .filter(|pollen| pollen.status == PollenStatus::Processing)
+            .count()
+    }
+
+    /// Publishes the liveness of the subscription to metrics and the status file.
+    ///
+    /// Attach requests of `pollenwall attach` are picked up here too.
+    fn watchdog_tick(&mut self) {
+        // Finished tasks are only kept for their results.
+        while self.background_tasks.try_join_next().is_some() {}
+        match attach::take_request(&self.app_folder_path) {
+            Ok(Some(selector)) => {
+                self.switch_attachment(selector);
+                self.publish_state();
+            }
+            Ok(None) => {}
+            Err(err) => warn!(error = %err, "failed to read attach request"),
+        }
+        self.expire_pollens(Instant::now());
+
+        let heartbeat_stale = self.heartbeat.is_stale(Instant::now());
+
+        self.metrics
+            .heartbeat_age
+            .set(self.heartbeat.age().map_or(-1.0, |age| age.as_secs_f64()));
+
+        let status = Status {
+            pid: std::process::id(),
+            updated_at: status::unix_secs(SystemTime::now()),
+            last_heartbeat_at: self.heartbeat.last_received_at().map(status::unix_secs),
+            heartbeat_timeout: self.heartbeat.timeout().as_secs(),
+            heartbeat_stale,
+            processing_pollens: self.processing_pollens_count(),
+            attached_pollen: self.pollen_uuid_to_attach.clone(),
+            attach_selector: self.attach_mode.then(|| self.attach_selector.to_string()),
+            processing: self
+                .dashboard_state()
+                .processing
+                .into_iter()
+                .map(|pollen| StatusPollen {
+                    uuid: pollen.uuid,
+                    model: pollen.model.map(str::to_owned),
+                    prompt: pollen.prompt,
+                })
+                .collect(),
+        };
+        if let Err(err) = status.write(&self.app_folder_path) {
+            warn!(error = %err, "failed to write status");
+        }
+        self.save_state();
+    }
+
+    async fn handle_message(&mut self, res: PubsubSubResponse) -> Result<()> {
+        let msg = match res.data {
+            Some(msg) => msg,
+            None => return Ok(()),
+        };
+        // Decode base64 response
+        let msg = match decode_msg(msg) {
+            Ok(msg) => msg,
+            Err(err) => {
+                debug!(error = %err, "ignored message which couldn't be decoded");
+                return Ok(());
+            }
+        };
+        // Filter `HEARTBEAT` messages in the stream
+        if msg.contains(HEARTBEAT) {
+            self.heartbeat.received(Instant::now());
+            return Ok(());
+        }
+        let hash = msg;
+
+        // Path for the current pollen output
+        let path_to_current_pollen_output = format!("/ipfs/{}/output", &hash);
+
+        // Unwrap is safe here because there will always be a topic.
+        let topic = match &*get_current_topic(&res.topic_ids.unwrap()) {
+            "done_pollen" => Topic::DonePollen,
+            "processing_pollen" => Topic::ProcessingPollen,
+            _ => Topic::Unknown,
+        };
+
+        // Ignore unknown topics
+        if let Topic::Unknown = topic {
+            return Ok(());
+        }
+        self.metrics
+            .pollens_seen
+            .with_label_values(&[topic.name()])
+            .inc();
+
+        // Get pollen uuid
+        if let Ok(BlockStatResponse {
+            key: pollen_uuid, ..
+        }) = self.client.block_stat(&format!("{}/input", &hash)).await
+        {
+            if topic == Topic::DonePollen && self.done.contains(&pollen_uuid) {
+                debug!(pollen = %pollen_uuid, "ignored duplicate done pollen");
+                return Ok(());
+            }
+            let text_input = get_text_input_from_pollen_uuid(&self.client, &pollen_uuid).await;
+            let model_type = get_model_type_from_pollen_uuid(&self.client, &pollen_uuid).await;
+
+            if let Some(pollen) = self.pollens.get_mut(&pollen_uuid) {
+                // Pollen is being tracked already so update its info
+                pollen.topic = topic.to_owned();
+                pollen.last_seen = Instant::now();
+                pollen.hash_of_current_iteration = hash.to_owned();
+                pollen.model_type = model_type;
+                pollen.text_input = text_input;
+                match pollen.status {
+                    // Ignore pollen if it once set as wallpaper
+                    // This would help filtering for duplicate done messages.
+                    PollenStatus::OnceSetAsWallpaper => match topic {
+                        // Topic::ProcessingPollen => {
+                        //     // TODO: Additional logic of attaching to a processing pollen may go here.
+                        //     if matches.is_present("attach") {
+                        //         if let Some(uuid) = &self.pollen_uuid_to_attach {
+                        //             if pollen_uuid != *uuid {
+                        //                 // Ignore pollens which are not attached.
+                        //                 // Else even if it has the same uuid set the new evolution state as wallpaper
+                        //                 return Ok(());
+                        //             } else {
+                        //                 // Renew status for keeping it attached
+                        //                 pollen.status = PollenStatus::Processing;
+                        //             }
+                        //         }
+                        //     }
+                        // }
+                        Topic::ProcessingPollen => {
+                            // TODO: Simplify this
+                            // Pass
+                        }
+                        Topic::DonePollen => {
+                            // Ignore done pollens which had been already set as wallpaper
+                            return Ok(());
+                        }
+                        _ => {
+                            unreachable!();
+                        }
+                    },
+                    // Attaching logic for
+                    _ => {
+                        pollen.status = match topic {
+                            Topic::ProcessingPollen => PollenStatus::Processing,
+                            Topic::DonePollen => PollenStatus::Done,
+                            _ => unreachable!(),
+                        }
+                    }
+                }
+            } else {
+                // Pollen not tracked yet, store it
+                // Since it is a done pollen tag it.
+                self.metrics
+                    .pollens_by_model
+                    .with_label_values(&[model_type.as_ref().map_or("none", Model::name)])
+                    .inc();
+                self.pollens.insert(
+                    pollen_uuid.to_owned(),
+                    PollenInfo::with_status(
+                        pollen_uuid.to_owned(),
+                        topic.to_owned(),
+                        hash.to_owned(),
+                        model_type,
+                        text_input,
+                        match topic {
+                            Topic::DonePollen => PollenStatus::Done,
+                            Topic::ProcessingPollen => PollenStatus::Processing,
+                            _ => unreachable!(),
+                        },
+                    ),
+                );
+            }
+
+            if self.archive && topic == Topic::DonePollen {
+                self.archive_pollen(&pollen_uuid, &hash);
+            }
+
+            // Find the latest evolution (image) of pollen
+            if let Ok(list_of_output_folder) =
+                self.client.file_ls(&path_to_current_pollen_output).await
+            {
+                let evolutions =
+                    evolution::evolutions(&list_of_output_folder, &self.media_policies);
+                if let Some(latest) =
+                    evolution::select(&evolutions, EvolutionStrategy::Latest, false)
+                {
+                    let processing_pollens_count = self.processing_pollens_count();
+                    self.metrics
+                        .processing_pollens
+                        .set(processing_pollens_count as i64);
+                    // We know that we have registered that pollen here so we can unwrap
+                    let pollen = self.pollens.get_mut(&pollen_uuid).unwrap();
+                    // Update pollen info
+                    pollen.last_polled_evolution = Some(PolledEvolutionInfo::from(latest));
+                    let status = pollen.status;
+                    let last_set_evolution = pollen.last_set_evolution.clone();
+                    self.publish_state();
+                    let selected = evolution::select(
+                        &evolutions,
+                        self.evolution_strategy,
+                        status == PollenStatus::Done,
+                    );
+                    match status {
+                        PollenStatus::Processing => {
+                            if self.attach_mode {
+                                // Attach to a processing pollen which the selector picks
+                                if self.pollen_uuid_to_attach.is_none() {
+                                    self.pick_pollen_to_attach(Instant::now());
+                                    self.publish_state();
+                                }
+                                // A processing pollen is picked here naturally
+                                if let Some(uuid) = &self.pollen_uuid_to_attach {
+                                    if pollen_uuid == *uuid {
+                                        let pollen_header = match selected {
+                                            Some(header)
+                                                if last_set_evolution.as_ref()
+                                                    != Some(&header.hash) =>
+                                            {
+                                                header
+                                            }
+                                            // Not the time for a new wallpaper according to the strategy
+                                            _ => return Ok(()),
+                                        };
+                                        // New iteration arrived
+                                        info!(
+                                            pollen = %pollen_uuid,
+                                            evolution = %pollen_header.name,
+                                            "new evolution of attached pollen arrived"
+                                        );
+                                        // A failed download is tried again with the next message.
+                                        self.set_as_wallpaper(
+                                            &pollen_uuid,
+                                            pollen_header,
+                                            WallpaperSource::Attached,
+                                            processing_pollens_count,
+                                        )
+                                        .await?;
+                                    } else {
+                                        // Ignore pollens which are not attached.
+                                        return Ok(());
                                     }
                                 } else {
-                                    // Ignore model which is not a CLIP+VQGAN
-                                    continue;
+                                    // No pollen id to attach..
+                                    // This might be unreachable
+                                    return Ok(());
+                                }
+                            }
+                        }
+                        PollenStatus::Done => {
+                            let mut source = WallpaperSource::Done;
+                            if self.attach_mode {
+                                if let Some(uuid) = &self.pollen_uuid_to_attach {
+                                    if pollen_uuid == *uuid {
+                                        // Attached pollen is done
+                                        // Empty the slot for a new one to attach
+                                        self.pollen_uuid_to_attach = None;
+                                        self.skipped.remove(&pollen_uuid);
+                                        source = WallpaperSource::Attached;
+                                    }
+                                } else if self
+                                    .attach_selector
+                                    .matches(&pollen_uuid, &self.pollens[&pollen_uuid])
+                                {
+                                    // Nothing attached yet, the pollens which the selector would pick count as attached.
+                                    source = WallpaperSource::Attached;
                                 }
-                            } else {
-                                // Couldn't ls the output folder, ignore pollen
-                                continue;
                             }
-                        } else {
-                            //Couldn't retrieve pollen uuid, then ignore this pollen.
-                            continue;
+                            if self.outputs.targets(source, self.attach_mode).is_empty() {
+                                // Block done pollens which no output shows.
+                                return Ok(());
+                            }
+
+                            // Done pollens always have a selected evolution when they have any.
+                            let pollen_header = selected.unwrap_or(latest);
+                            info!(
+                                pollen = %pollen_uuid,
+                                evolution = %pollen_header.name,
+                                "pollen arrived"
+                            );
+
+                            if !self
+                                .set_as_wallpaper(
+                                    &pollen_uuid,
+                                    pollen_header,
+                                    source,
+                                    processing_pollens_count,
+                                )
+                                .await?
+                            {
+                                // Kept so that a repeated done message tries again.
+                                return Ok(());
+                            }
+                            if !self.paused {
+                                self.animate_pollen(&pollen_uuid, &list_of_output_folder);
+                            }
+
+                            // Update pollen info
+                            if let Some(pollen) = self.pollens.get_mut(&pollen_uuid) {
+                                pollen.status = PollenStatus::OnceSetAsWallpaper;
+                            }
+
+                            // Remove from internal store with its uuid.
+                            self.pollens.remove_entry(&pollen_uuid);
+                            self.done.push_front(pollen_uuid.clone());
+                            self.done.truncate(DONE_POLLENS_REMEMBERED);
+                            self.pick_pollen_to_attach(Instant::now());
+                            self.publish_state();
                         }
+                        _ => unreachable!(),
                     }
+                } else {
+                    // No evolution in a format which we handle
+                    debug!(pollen = %pollen_uuid, "no evolution found in pollen output");
+                    return Ok(());
                 }
+            } else {
+                // Couldn't ls the output folder, ignore pollen
+                debug!(pollen = %pollen_uuid, "couldn't list pollen output");
+                return Ok(());
             }
-            Err(err) => {
-                // Pubsub error
-                eprintln!("{:?}", err);
-                continue;
-            }
+        } else {
+            //Couldn't retrieve pollen uuid, then ignore this pollen.
+            debug!(%hash, "couldn't retrieve pollen uuid");
+            return Ok(());
         }
+        Ok(())
     }
-    Ok(())
+}
+
+/// The instant `unix_secs` seconds since unix epoch were, `now` and `now_instant` being the current time.
+///
+/// Times in the future are now, so are the ones too old for the monotonic clock.
+fn instant_at(now: SystemTime, now_instant: Instant, unix_secs: u64) -> Instant {
+    let ago = status::unix_secs(now).saturating_sub(unix_secs);
+    now_instant
+        .checked_sub(Duration::from_secs(ago))
+        .unwrap_or(now_instant)
+}
+
+/// Seconds since unix epoch at `instant`, `now` and `now_instant` being the current time.
+fn unix_secs_at(now: SystemTime, now_instant: Instant, instant: Instant) -> u64 {
+    status::unix_secs(now).saturating_sub(now_instant.saturating_duration_since(instant).as_secs())
 }
 
 fn decode_msg(input: String) -> Result<String> {
@@ -653,7 +2647,7 @@ fn decode_msg(input: String) -> Result<String> {
 
 fn get_app_folder_path(home: &str) -> PathBuf {
     let mut app_folder_path = PathBuf::new();
-    app_folder_path.push(&home);
+    app_folder_path.push(home);
     app_folder_path.push(APP_FOLDER_NAME);
     app_folder_path
 }
@@ -663,140 +2657,107 @@ fn get_current_topic(topics: &[String]) -> String {
     topics.first().unwrap().clone()
 }
 
-fn get_the_latest_image_according_to_numbering(
-    response: &'_ FileLsResponse,
-) -> Option<&'_ IpfsHeader> {
-    let (result, _) = response.objects.values().next().unwrap().links.iter().fold(
-        (None, 0_usize),
-        |mut index: (Option<&IpfsHeader>, usize), header| {
-            // Extract the digits from the name which has the format `ccc..._ddddd.jpg` example `processing_00005.jpg`.
-            let extracted: String = header
-                .name
-                .chars()
-                .filter(|c| c.is_numeric() && header.name.contains(".jpg"))
-                .collect();
-            if !extracted.is_empty() {
-                // Parse it as number, we know that it is numeric so unwrap is fine.
-                let current_index = extracted.parse::<usize>().unwrap();
-                // Continue folding to find the last one
-                if current_index > index.1 {
-                    index.1 = current_index;
-                    index.0 = Some(header);
-                }
-            }
-            index
-        },
-    );
-    result
-}
-
+/// Downloads the evolution into the cache unless it is cached already and returns its path.
 async fn save_pollen(
     client: &IpfsClient,
     download_hash: &str,
-    save_path: &Path,
-) -> Result<Option<SystemTime>> {
-    let mut file = tokio::fs::File::create(save_path).await?;
+    name: &str,
+    cache: &mut Cache,
+    metrics: &Metrics,
+) -> Result<PathBuf> {
+    if let Some(path) = cache.get(download_hash) {
+        debug!(cid = %download_hash, path = %path.display(), "evolution is cached");
+        return Ok(path);
+    }
+    let save_path = cache.path(download_hash, name);
+    if let Some(folder_path) = save_path.parent() {
+        tokio::fs::create_dir_all(folder_path).await?;
+    }
+    // Written next to the file first so that an interrupted download is never taken for the evolution.
+    let partial_path = files::temp_path(&save_path);
+    let mut file = tokio::fs::File::create(&partial_path).await?;
+    let download_timer = metrics.download_duration.start_timer();
+    let mut written = 0;
 
     // TODO: This should be unnecessary learn to use Bytes crate see hack below
     let mut cnt = 0;
 
     // Download and write the file
     let mut download_stream = client.get(download_hash);
-    while let Some(Ok(buf)) = download_stream.next().await {
+    while let Some(buf) = download_stream.next().await {
+        let buf = match buf {
+            Ok(buf) => buf,
+            Err(err) => {
+                drop(file);
+                let _ = tokio::fs::remove_file(&partial_path).await;
+                bail!("Couldn't download \"{}\": {}", download_hash, err);
+            }
+        };
         if cnt == 0 {
             // Hack, I am too tired to learn to get the contents properly
             // First 512 bytes shouldn't be written.
             file.write_all(&buf.slice(512..)).await?;
+            written += buf.len().saturating_sub(512);
         } else {
             file.write_all(&buf.slice(0..)).await?;
+            written += buf.len();
         }
         cnt += 1;
     }
 
-    if let Ok(metadata) = tokio::fs::metadata(save_path).await {
-        if let Ok(created) = metadata.created() {
-            file.shutdown().await?;
-            return Ok(Some(created));
-        }
-    }
+    download_timer.observe_duration();
+    metrics.downloads.inc();
+    metrics.download_bytes.inc_by(written as u64);
+
     file.shutdown().await?;
-    Ok(None)
+    tokio::fs::rename(&partial_path, &save_path).await?;
+    cache.insert(download_hash, &save_path);
+    Ok(save_path)
 }
 
-fn set_wallpaper_with_delay(
-    wallpaper_path: PathBuf,
-    ipfs_hash: String,
+/// Sets the wallpapers of the outputs, `targets` are output names with the wallpaper for them.
+async fn set_wallpaper_with_delay(
+    entry: HistoryEntry,
+    pollen_path: PathBuf,
+    targets: Vec<(Option<String>, PathBuf)>,
+    backend: Arc<Backend>,
     processing_pollens_count: usize,
+    metrics: Arc<Metrics>,
+    events: mpsc::UnboundedSender<WallpaperSet>,
 ) {
-    tokio::spawn(async move {
-        // We need to delay setting the wallpaper a little for Windows
-        // or there will be a black screen set.
-        tokio::time::sleep(tokio::time::Duration::from_millis(WALLPAPER_SET_DELAY)).await;
-
-        dbg!(wallpaper_path.to_str());
-        match wallpaper::set_from_path(wallpaper_path.to_str().unwrap()) {
+    // We need to delay setting the wallpaper a little for Windows
+    // or there will be a black screen set.
+    tokio::time::sleep(tokio::time::Duration::from_millis(WALLPAPER_SET_DELAY)).await;
+
+    // Applied if any of the outputs got it.
+    let mut applied = false;
+    for (output, wallpaper_path) in &targets {
+        let output_name = output.as_deref().unwrap_or("*");
+        match backend.set(output.as_deref(), wallpaper_path).await {
             // Notify user
             Ok(_) => {
-                println!("{}", "Wallpaper set with the new pollen!".magenta());
-                println!(
-                    "{}{}",
-                    "You may find this pollen at: ".yellow(),
-                    format!("https://ipfs.io/ipfs/{}", &ipfs_hash)
-                );
-                println!(
-                    "{}{}{}",
-                    "Currently ".yellow(),
-                    processing_pollens_count.to_string().green(),
-                    " pollens are processing..".yellow(),
+                metrics.wallpaper_sets.with_label_values(&["success"]).inc();
+                info!(
+                    output = output_name,
+                    path = %wallpaper_path.display(),
+                    url = %format!("https://ipfs.io/ipfs/{}", &entry.cid),
+                    processing_pollens_count,
+                    "wallpaper set with the new pollen"
                 );
+                applied = true;
             }
             Err(err) => {
-                eprintln!("{}{}", " Failed to set wallpaper: ".red(), err,);
+                metrics.wallpaper_sets.with_label_values(&["failure"]).inc();
+                error!(output = output_name, path = %wallpaper_path.display(), error = %err, "failed to set wallpaper");
             }
         }
-    });
-}
-
-async fn clear_previous_pollens(dir_path: &Path, current_creation_time: &SystemTime) -> Result<()> {
-    if let Ok(mut directory_reader) = tokio::fs::read_dir(&dir_path).await {
-        while let Ok(Some(entry)) = directory_reader.next_entry().await {
-            let path = entry.path().clone();
-
-            if let Ok(metadata) = tokio::fs::metadata(&entry.path()).await {
-                if metadata.is_file() {
-                    if let Ok(entry_creation_time) = metadata.created() {
-                        if current_creation_time.elapsed().unwrap().as_millis()
-                            < entry_creation_time.elapsed().unwrap().as_millis()
-                        {
-                            if let Some(ex) = path.extension() {
-                                // TODO: This might be extended
-                                if ex == "jpg" {
-                                    #[cfg(target_os = "linux")]
-                                    // Needed in Linux because for a split second when the previous
-                                    // wallpaper is deleted the screen turns blue.
-                                    tokio::spawn(async move {
-                                        tokio::time::sleep(tokio::time::Duration::from_millis(
-                                            WALLPAPER_SET_DELAY + 500,
-                                        ))
-                                        .await;
-                                        // TODO: Handle this result
-                                        tokio::fs::remove_file(&path).await;
-                                    })
-                                    .await?;
-
-                                    #[cfg(not(target_os = "linux"))]
-                                    // Others are fine with this.
-                                    tokio::fs::remove_file(&path).await?;
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        return Ok(());
     }
-    Err(anyhow!("Failed to read directory"))
+    // Tracker might be gone if the app is quitting.
+    let _ = events.send(WallpaperSet {
+        entry,
+        pollen_path,
+        applied,
+    });
 }
 
 async fn get_model_type_from_pollen_uuid(client: &IpfsClient, pollen_uuid: &str) -> Option<Model> {
@@ -812,7 +2773,7 @@ async fn get_model_type_from_pollen_uuid(client: &IpfsClient, pollen_uuid: &str)
     }
 
     if model_name.is_empty() {
-        // eprintln!("{}", "No model info found".red());
+        debug!(pollen = %pollen_uuid, "no model info found");
         return None;
     }
 
@@ -821,7 +2782,7 @@ async fn get_model_type_from_pollen_uuid(client: &IpfsClient, pollen_uuid: &str)
         "\"ViT-B/32\"" => Some(Model::VitB32),
         "\"QoL tweaks for nshepperd…P Guided Diffusion v2.4\"" => Some(Model::GuidedDiffusion),
         _ => {
-            // eprintln!("{}{}", "Found unknown model: ".red(), model_name.yellow());
+            debug!(pollen = %pollen_uuid, model = %model_name, "found unknown model");
             Some(Model::Unknown)
         }
     }
@@ -840,9 +2801,252 @@ async fn get_text_input_from_pollen_uuid(client: &IpfsClient, pollen_uuid: &str)
     }
 
     if text_input.is_empty() {
-        // eprintln!("{}", "No text input found".red());
+        debug!(pollen = %pollen_uuid, "no text input found");
         None
     } else {
         Some(text_input)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use files::TempFolder;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn tracker() -> (TempFolder, PollenTracker) {
+        let (folder, tracker, _) = tracker_with_events();
+        (folder, tracker)
+    }
+
+    fn tracker_with_events() -> (
+        TempFolder,
+        PollenTracker,
+        mpsc::UnboundedReceiver<WallpaperSet>,
+    ) {
+        let app_folder_path = TempFolder::new("tracker");
+        let (tracker, wallpaper_events_rx) = tracker_in(&app_folder_path);
+        (app_folder_path, tracker, wallpaper_events_rx)
+    }
+
+    fn tracker_in(
+        app_folder_path: &Path,
+    ) -> (PollenTracker, mpsc::UnboundedReceiver<WallpaperSet>) {
+        let (wallpaper_events, wallpaper_events_rx) = mpsc::unbounded_channel();
+        let mut tracker = PollenTracker::new(
+            IpfsClient::default(),
+            app_folder_path.to_path_buf(),
+            true,
+            AttachSelector::First,
+            Arc::new(Metrics::new().unwrap()),
+            Heartbeat::new(MINUTE),
+            wallpaper_events,
+        );
+        tracker.pollen_ttl = 10 * MINUTE;
+        tracker.attach_timeout = 2 * MINUTE;
+        (tracker, wallpaper_events_rx)
+    }
+
+    // A processing pollen with an evolution, last heard of at `last_seen`.
+    fn add_pollen(tracker: &mut PollenTracker, uuid: &str, last_seen: Instant) {
+        tracker.pollens.insert(
+            uuid.to_owned(),
+            PollenInfo {
+                id: uuid.to_owned(),
+                topic: Topic::ProcessingPollen,
+                last_polled_evolution: Some(PolledEvolutionInfo::new(
+                    "hash".to_owned(),
+                    "evolution_1.jpg".to_owned(),
+                    1,
+                )),
+                last_seen,
+                ..PollenInfo::default()
+            },
+        );
+    }
+
+    #[test]
+    fn rejects_too_long_durations() {
+        let folder = TempFolder::new("settings");
+        for arg in [
+            "--attach-timeout",
+            "--pollen-ttl",
+            "--keep-size",
+            "--keep-days",
+            "--cache-size",
+        ] {
+            let args = app().get_matches_from(["pollenwall", arg, &u64::MAX.to_string()]);
+            assert!(settings(&args, &folder).is_err());
+        }
+    }
+
+    const NOW: u64 = 1_700_000_000;
+
+    #[test]
+    fn converts_times_between_clocks() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(NOW);
+        // Far enough from the start of the monotonic clock to go back an hour.
+        let now_instant = Instant::now() + 60 * MINUTE;
+        let ago = instant_at(now, now_instant, NOW - 90);
+        assert_eq!(now_instant - ago, Duration::from_secs(90));
+        assert_eq!(unix_secs_at(now, now_instant, ago), NOW - 90);
+        // Saved in the future, after the clock was set back.
+        assert_eq!(instant_at(now, now_instant, NOW + 90), now_instant);
+        assert_eq!(unix_secs_at(now, now_instant, now_instant + MINUTE), NOW);
+    }
+
+    #[tokio::test]
+    async fn restores_saved_state() {
+        let (folder, mut tracker) = tracker();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(NOW);
+        let now_instant = Instant::now() + 60 * MINUTE;
+        add_pollen(&mut tracker, "attached", now_instant - 5 * MINUTE);
+        add_pollen(&mut tracker, "skipped", now_instant - MINUTE);
+        tracker.pollen_uuid_to_attach = Some("attached".to_owned());
+        tracker.skipped.insert("skipped".to_owned());
+        tracker.done.push_front("done".to_owned());
+        tracker.state(now, now_instant).write(&folder).unwrap();
+
+        let (mut restored, _) = tracker_in(&folder);
+        restored.restore_state(State::load(&folder), now, now_instant);
+        assert_eq!(restored.pollen_uuid_to_attach.as_deref(), Some("attached"));
+        assert!(restored.skipped.contains("skipped"));
+        assert_eq!(restored.done, ["done"]);
+        let pollen = &restored.pollens["attached"];
+        assert_eq!(now_instant - pollen.last_seen, 5 * MINUTE);
+        assert_eq!(
+            pollen
+                .last_polled_evolution
+                .as_ref()
+                .map(|evolution| evolution.name.as_str()),
+            Some("evolution_1.jpg")
+        );
+    }
+
+    #[tokio::test]
+    async fn leaves_out_stale_pollens_when_restoring() {
+        let (folder, tracker) = tracker();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(NOW);
+        let now_instant = Instant::now() + 60 * MINUTE;
+        let mut state = tracker.state(now, now_instant);
+        // The oldest ones may be out of reach of the monotonic clock.
+        for (uuid, minutes_ago) in [("stale", 11), ("ancient", NOW / 60), ("fresh", 9)] {
+            state.pollens.push(PollenState {
+                uuid: uuid.to_owned(),
+                model: None,
+                text_input: None,
+                hash: "hash".to_owned(),
+                evolution: None,
+                last_set_evolution: None,
+                first_seen: NOW - minutes_ago * 60,
+                last_seen: NOW - minutes_ago * 60,
+            });
+        }
+        state.attached_pollen = Some("stale".to_owned());
+
+        let (mut restored, _) = tracker_in(&folder);
+        restored.restore_state(state, now, now_instant);
+        assert!(!restored.pollens.contains_key("stale"));
+        assert!(!restored.pollens.contains_key("ancient"));
+        assert!(restored.pollens.contains_key("fresh"));
+        assert_eq!(restored.pollen_uuid_to_attach, None);
+    }
+
+    #[tokio::test]
+    async fn takes_in_wallpapers_set_while_quitting() {
+        let (folder, mut tracker, mut wallpaper_events) = tracker_with_events();
+        let path = folder.join("wallpaper.jpg");
+        let entry = HistoryEntry {
+            pollen_uuid: "pollen".to_owned(),
+            cid: "cid".to_owned(),
+            evolution: "evolution_1.jpg".to_owned(),
+            path: path.clone(),
+            prompt: None,
+            model: None,
+            set_at: 0,
+            favourite: false,
+            files: vec![path.clone()],
+        };
+        tracker.background_tasks.spawn(set_wallpaper_with_delay(
+            entry,
+            path.clone(),
+            vec![(None, path)],
+            Arc::new(Backend::new(true, None)),
+            0,
+            tracker.metrics.clone(),
+            tracker.wallpaper_events.clone(),
+        ));
+
+        tracker.finish_background_tasks(&mut wallpaper_events).await;
+        assert!(tracker.background_tasks.is_empty());
+        assert_eq!(
+            tracker
+                .history
+                .recent(1)
+                .map(|entry| entry.pollen_uuid.as_str())
+                .collect::<Vec<_>>(),
+            ["pollen"]
+        );
+    }
+
+    #[tokio::test]
+    async fn turns_attach_mode_on_when_reloaded() {
+        let (folder, mut tracker) = tracker();
+        tracker.attach_mode = false;
+        add_pollen(&mut tracker, "pollen", Instant::now());
+
+        let args = app().get_matches_from(["pollenwall", "--attach"]);
+        let attached = settings(&args, &folder).unwrap();
+        // The selector stays the default one.
+        assert_eq!(attached.attach_selector, tracker.attach_selector);
+        tracker.apply_settings(attached);
+        assert!(tracker.attach_mode);
+        assert_eq!(tracker.pollen_uuid_to_attach.as_deref(), Some("pollen"));
+
+        let args = app().get_matches_from(["pollenwall"]);
+        tracker.apply_settings(settings(&args, &folder).unwrap());
+        assert!(!tracker.attach_mode);
+        assert_eq!(tracker.pollen_uuid_to_attach, None);
+    }
+
+    #[tokio::test]
+    async fn forgets_stale_pollens_and_their_skips() {
+        let (_folder, mut tracker) = tracker();
+        let start = Instant::now();
+        add_pollen(&mut tracker, "stale", start);
+        add_pollen(&mut tracker, "fresh", start + 10 * MINUTE);
+        tracker.skipped.insert("stale".to_owned());
+        tracker.skipped.insert("fresh".to_owned());
+
+        let now = start + 11 * MINUTE;
+        tracker.expire_pollens(now);
+        assert!(!tracker.pollens.contains_key("stale"));
+        assert!(tracker.pollens.contains_key("fresh"));
+        assert!(!tracker.skipped.contains("stale"));
+        assert!(tracker.skipped.contains("fresh"));
+
+        // Attached again once it is heard of after expiring.
+        add_pollen(&mut tracker, "stale", now);
+        tracker.pick_pollen_to_attach(now);
+        assert_eq!(tracker.pollen_uuid_to_attach.as_deref(), Some("stale"));
+    }
+
+    #[tokio::test]
+    async fn detaches_silent_pollen() {
+        let (_folder, mut tracker) = tracker();
+        let start = Instant::now();
+        add_pollen(&mut tracker, "silent", start);
+        add_pollen(&mut tracker, "heard", start + 2 * MINUTE);
+        tracker.pollen_uuid_to_attach = Some("silent".to_owned());
+
+        tracker.expire_pollens(start + 3 * MINUTE);
+        // Still tracked, but another pollen is attached.
+        assert!(tracker.pollens.contains_key("silent"));
+        assert_eq!(tracker.pollen_uuid_to_attach.as_deref(), Some("heard"));
+
+        // Silent too after the attach timeout.
+        tracker.expire_pollens(start + 5 * MINUTE);
+        assert_eq!(tracker.pollen_uuid_to_attach, None);
+    }
+}
@@ -0,0 +1,236 @@
+use anyhow::{bail, Result};
+use std::{collections::HashMap, path::Path, str::FromStr};
+
+/// Formats of the pollen outputs which pollenwall knows about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MediaType {
+    Jpeg,
+    Png,
+    Webp,
+    Gif,
+    Mp4,
+    Webm,
+}
+
+impl MediaType {
+    pub const ALL: [MediaType; 6] = [
+        MediaType::Jpeg,
+        MediaType::Png,
+        MediaType::Webp,
+        MediaType::Gif,
+        MediaType::Mp4,
+        MediaType::Webm,
+    ];
+
+    /// Media type of a file according to its extension.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let extension = Path::new(name).extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "jpg" | "jpeg" => Some(MediaType::Jpeg),
+            "png" => Some(MediaType::Png),
+            "webp" => Some(MediaType::Webp),
+            "gif" => Some(MediaType::Gif),
+            "mp4" => Some(MediaType::Mp4),
+            "webm" => Some(MediaType::Webm),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MediaType::Jpeg => "jpg",
+            MediaType::Png => "png",
+            MediaType::Webp => "webp",
+            MediaType::Gif => "gif",
+            MediaType::Mp4 => "mp4",
+            MediaType::Webm => "webm",
+        }
+    }
+
+    pub fn is_video(&self) -> bool {
+        matches!(self, MediaType::Mp4 | MediaType::Webm)
+    }
+
+    /// Still images which can be decoded as frames, gif counts as a video here.
+    pub fn is_still_image(&self) -> bool {
+        matches!(self, MediaType::Jpeg | MediaType::Png | MediaType::Webp)
+    }
+}
+
+impl FromStr for MediaType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match MediaType::from_name(&format!("media.{}", s)) {
+            Some(media_type) => Ok(media_type),
+            None => bail!(
+                "Unknown media type \"{}\", expected one of \"jpg\", \"png\", \"webp\", \"gif\", \"mp4\" or \"webm\"",
+                s
+            ),
+        }
+    }
+}
+
+/// What to do with an evolution of a media type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediaPolicy {
+    /// Set it as a static wallpaper.
+    Wallpaper,
+    /// Loop it with the animated wallpaper command.
+    Animate,
+    /// Ignore evolutions of this type.
+    Skip,
+}
+
+impl FromStr for MediaPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "wallpaper" => Ok(MediaPolicy::Wallpaper),
+            "animate" => Ok(MediaPolicy::Animate),
+            "skip" => Ok(MediaPolicy::Skip),
+            _ => bail!(
+                "Unknown media policy \"{}\", expected one of \"wallpaper\", \"animate\" or \"skip\"",
+                s
+            ),
+        }
+    }
+}
+
+/// Policies per media type.
+///
+/// Images are set as wallpaper, gifs and videos are animated. Without an animated
+/// wallpaper command animating falls back to a static wallpaper for gifs and to skipping for videos.
+#[derive(Debug, Clone)]
+pub struct MediaPolicies {
+    policies: HashMap<MediaType, MediaPolicy>,
+    can_animate: bool,
+}
+
+impl MediaPolicies {
+    /// `overrides` are in the form of `<media-type>=<policy>`, for example `mp4=skip`.
+    pub fn new<'a>(overrides: impl Iterator<Item = &'a str>, can_animate: bool) -> Result<Self> {
+        let mut policies: HashMap<MediaType, MediaPolicy> = MediaType::ALL
+            .iter()
+            .map(|media_type| {
+                let policy = if media_type.is_still_image() {
+                    MediaPolicy::Wallpaper
+                } else {
+                    MediaPolicy::Animate
+                };
+                (*media_type, policy)
+            })
+            .collect();
+
+        for media_policy in overrides {
+            let (media_type, policy) = match media_policy.split_once('=') {
+                Some((media_type, policy)) => (
+                    media_type.parse::<MediaType>()?,
+                    policy.parse::<MediaPolicy>()?,
+                ),
+                None => bail!(
+                    "Invalid media policy \"{}\", expected \"<media-type>=<policy>\"",
+                    media_policy
+                ),
+            };
+            if media_type.is_video() && policy == MediaPolicy::Wallpaper {
+                bail!("Videos can't be set as a static wallpaper, use \"animate\" or \"skip\" for \"{}\"", media_type.name());
+            }
+            policies.insert(media_type, policy);
+        }
+
+        Ok(Self {
+            policies,
+            can_animate,
+        })
+    }
+
+    /// Policy for a file, unknown formats are skipped.
+    pub fn policy(&self, name: &str) -> MediaPolicy {
+        let media_type = match MediaType::from_name(name) {
+            Some(media_type) => media_type,
+            None => return MediaPolicy::Skip,
+        };
+        match self.policies[&media_type] {
+            MediaPolicy::Animate if !self.can_animate => {
+                if media_type.is_video() {
+                    MediaPolicy::Skip
+                } else {
+                    MediaPolicy::Wallpaper
+                }
+            }
+            policy => policy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_media_types_from_names() {
+        assert_eq!(
+            MediaType::from_name("evolution_3.JPEG"),
+            Some(MediaType::Jpeg)
+        );
+        assert_eq!(MediaType::from_name("loop.webm"), Some(MediaType::Webm));
+        assert_eq!(MediaType::from_name("evolution_3"), None);
+        assert_eq!(MediaType::from_name("notes.txt"), None);
+        for media_type in MediaType::ALL {
+            assert_eq!(media_type.name().parse::<MediaType>().unwrap(), media_type);
+        }
+    }
+
+    #[test]
+    fn falls_back_without_an_animated_wallpaper_command() {
+        use MediaPolicy::{Animate, Skip, Wallpaper};
+        // Override, file, policy with and without an animated wallpaper command.
+        let cases = [
+            (None, "evolution.jpg", Wallpaper, Wallpaper),
+            (None, "evolution.png", Wallpaper, Wallpaper),
+            (None, "evolution.webp", Wallpaper, Wallpaper),
+            (None, "evolution.gif", Animate, Wallpaper),
+            (None, "evolution.mp4", Animate, Skip),
+            (None, "evolution.webm", Animate, Skip),
+            (None, "evolution.txt", Skip, Skip),
+            (Some("png=animate"), "evolution.png", Animate, Wallpaper),
+            (Some("png=skip"), "evolution.png", Skip, Skip),
+            (Some("gif=wallpaper"), "evolution.gif", Wallpaper, Wallpaper),
+            (Some("gif=skip"), "evolution.gif", Skip, Skip),
+            (Some("mp4=skip"), "evolution.mp4", Skip, Skip),
+            (Some("webm=animate"), "evolution.webm", Animate, Skip),
+        ];
+        for (media_policy, name, animated, not_animated) in cases {
+            for (can_animate, expected) in [(true, animated), (false, not_animated)] {
+                let policies = MediaPolicies::new(media_policy.into_iter(), can_animate).unwrap();
+                assert_eq!(
+                    policies.policy(name),
+                    expected,
+                    "{:?} {} {}",
+                    media_policy,
+                    name,
+                    can_animate
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_media_policies() {
+        for media_policy in [
+            "mp4=wallpaper",
+            "webm=wallpaper",
+            "mp4",
+            "bmp=skip",
+            "gif=loop",
+        ] {
+            assert!(
+                MediaPolicies::new(std::iter::once(media_policy), true).is_err(),
+                "{}",
+                media_policy
+            );
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use anyhow::Result;
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use prometheus::{
+    Encoder, Gauge, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+use tracing::{error, info};
+
+const NAMESPACE: &str = "pollenwall";
+const METRICS_PATH: &str = "/metrics";
+
+/// Counters and gauges exposed in prometheus text format when `--metrics-addr` is given.
+///
+/// Metrics are always collected, serving them is optional.
+pub struct Metrics {
+    registry: Registry,
+    pub pollens_seen: IntCounterVec,
+    pub pollens_by_model: IntCounterVec,
+    pub downloads: IntCounter,
+    pub download_bytes: IntCounter,
+    pub download_duration: Histogram,
+    pub download_failures: IntCounter,
+    pub wallpaper_sets: IntCounterVec,
+    pub wallpapers_rejected: IntCounterVec,
+    pub processing_pollens: IntGauge,
+    pub pubsub_reconnects: IntCounter,
+    pub heartbeat_age: Gauge,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new();
+
+        let pollens_seen = IntCounterVec::new(
+            Opts::new("pollens_seen_total", "Pollen messages received per topic.")
+                .namespace(NAMESPACE),
+            &["topic"],
+        )?;
+        let pollens_by_model = IntCounterVec::new(
+            Opts::new(
+                "pollens_by_model_total",
+                "Distinct pollens tracked per model.",
+            )
+            .namespace(NAMESPACE),
+            &["model"],
+        )?;
+        let downloads = IntCounter::with_opts(
+            Opts::new("downloads_total", "Pollen evolutions downloaded.").namespace(NAMESPACE),
+        )?;
+        let download_bytes = IntCounter::with_opts(
+            Opts::new(
+                "download_bytes_total",
+                "Bytes written for downloaded pollens.",
+            )
+            .namespace(NAMESPACE),
+        )?;
+        let download_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "download_duration_seconds",
+                "Time it takes to download a pollen evolution.",
+            )
+            .namespace(NAMESPACE)
+            .buckets(vec![0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]),
+        )?;
+        let download_failures = IntCounter::with_opts(
+            Opts::new(
+                "download_failures_total",
+                "Pollen evolutions which couldn't be downloaded.",
+            )
+            .namespace(NAMESPACE),
+        )?;
+        let wallpaper_sets = IntCounterVec::new(
+            Opts::new(
+                "wallpaper_sets_total",
+                "Attempts to set the wallpaper per result.",
+            )
+            .namespace(NAMESPACE),
+            &["result"],
+        )?;
+        let wallpapers_rejected = IntCounterVec::new(
+            Opts::new(
+                "wallpapers_rejected_total",
+                "Evolutions which didn't pass the quality gate per reason.",
+            )
+            .namespace(NAMESPACE),
+            &["reason"],
+        )?;
+        let processing_pollens = IntGauge::with_opts(
+            Opts::new(
+                "processing_pollens",
+                "Pollens which are currently processing.",
+            )
+            .namespace(NAMESPACE),
+        )?;
+        let pubsub_reconnects = IntCounter::with_opts(
+            Opts::new(
+                "pubsub_reconnects_total",
+                "Times the pubsub subscription was re-established.",
+            )
+            .namespace(NAMESPACE),
+        )?;
+        let heartbeat_age = Gauge::with_opts(
+            Opts::new(
+                "heartbeat_age_seconds",
+                "Seconds since the last heartbeat, -1 if none received yet.",
+            )
+            .namespace(NAMESPACE),
+        )?;
+
+        registry.register(Box::new(pollens_seen.clone()))?;
+        registry.register(Box::new(pollens_by_model.clone()))?;
+        registry.register(Box::new(downloads.clone()))?;
+        registry.register(Box::new(download_bytes.clone()))?;
+        registry.register(Box::new(download_duration.clone()))?;
+        registry.register(Box::new(download_failures.clone()))?;
+        registry.register(Box::new(wallpaper_sets.clone()))?;
+        registry.register(Box::new(wallpapers_rejected.clone()))?;
+        registry.register(Box::new(processing_pollens.clone()))?;
+        registry.register(Box::new(pubsub_reconnects.clone()))?;
+        registry.register(Box::new(heartbeat_age.clone()))?;
+
+        Ok(Self {
+            registry,
+            pollens_seen,
+            pollens_by_model,
+            downloads,
+            download_bytes,
+            download_duration,
+            download_failures,
+            wallpaper_sets,
+            wallpapers_rejected,
+            processing_pollens,
+            pubsub_reconnects,
+            heartbeat_age,
+        })
+    }
+
+    /// Encodes all metrics in prometheus text format.
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn respond(&self, req: Request<Body>) -> Response<Body> {
+        if req.uri().path() != METRICS_PATH {
+            let mut not_found = Response::new(Body::empty());
+            *not_found.status_mut() = StatusCode::NOT_FOUND;
+            return not_found;
+        }
+        match self.encode() {
+            Ok(buffer) => {
+                let mut response = Response::new(Body::from(buffer));
+                if let Ok(content_type) = TextEncoder::new().format_type().parse() {
+                    response.headers_mut().insert(CONTENT_TYPE, content_type);
+                }
+                response
+            }
+            Err(err) => {
+                error!(error = %err, "failed to encode metrics");
+                let mut internal_error = Response::new(Body::empty());
+                *internal_error.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                internal_error
+            }
+        }
+    }
+}
+
+/// Serves metrics on `http://<addr>/metrics` until the app exits.
+pub async fn serve(addr: SocketAddr, metrics: Arc<Metrics>) -> Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let response = metrics.respond(req);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    info!(%addr, "serving metrics");
+    server.await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn exposes_metrics_in_text_format() {
+        let metrics = Metrics::new().unwrap();
+        metrics.downloads.inc();
+        metrics.wallpaper_sets.with_label_values(&["ok"]).inc_by(2);
+
+        let request = |path: &str| Request::get(path).body(Body::empty()).unwrap();
+        let response = metrics.respond(request(METRICS_PATH));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(
+            text.contains("\npollenwall_downloads_total 1\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains("\npollenwall_wallpaper_sets_total{result=\"ok\"} 2\n"));
+        assert!(text.contains("# TYPE pollenwall_download_duration_seconds histogram"));
+
+        let response = metrics.respond(request("/"));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,406 @@
+use crate::{
+    backend::Backend,
+    postprocess,
+    screen::{self, OutputGeometry},
+};
+use anyhow::{bail, Result};
+use image::imageops::FilterType;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Which wallpapers an output shows.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputSource {
+    /// Every wallpaper, as when there is a single output.
+    #[default]
+    All,
+    /// Evolutions of the attached pollen.
+    Attached,
+    /// Done pollens, even while another pollen is attached.
+    Done,
+}
+
+impl OutputSource {
+    /// Whether the output shows a wallpaper from `source`.
+    ///
+    /// In attach mode only the attached pollen is shown on outputs which show every wallpaper.
+    pub fn accepts(&self, source: WallpaperSource, attach_mode: bool) -> bool {
+        match self {
+            OutputSource::All => source == WallpaperSource::Attached || !attach_mode,
+            OutputSource::Attached => source == WallpaperSource::Attached,
+            OutputSource::Done => source == WallpaperSource::Done,
+        }
+    }
+}
+
+impl FromStr for OutputSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "all" => Ok(OutputSource::All),
+            "attached" => Ok(OutputSource::Attached),
+            "done" => Ok(OutputSource::Done),
+            _ => bail!(
+                "Unknown output source \"{}\", expected one of \"all\", \"attached\" or \"done\"",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for OutputSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OutputSource::All => "all",
+            OutputSource::Attached => "attached",
+            OutputSource::Done => "done",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Where a wallpaper comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WallpaperSource {
+    /// The attached pollen, including its final evolution.
+    Attached,
+    /// A done pollen which isn't attached.
+    Done,
+}
+
+/// An output, a monitor in the screen layout.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    /// Name the wallpaper command knows the output by, `None` stands for every output.
+    pub name: Option<String>,
+    pub source: OutputSource,
+    pub geometry: Option<OutputGeometry>,
+}
+
+impl FromStr for Output {
+    type Err = anyhow::Error;
+
+    /// Parses `<name>[@<width>x<height>+<x>+<y>][=<source>]`, for example `DP-1=attached`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (output, source) = match s.split_once('=') {
+            Some((output, source)) => (output, source.parse::<OutputSource>()?),
+            None => (s, OutputSource::All),
+        };
+        let (name, geometry) = match output.split_once('@') {
+            Some((name, geometry)) => (name, Some(geometry.parse::<OutputGeometry>()?)),
+            None => (output, None),
+        };
+        if name.is_empty() {
+            bail!("Output \"{}\" should have a name", s);
+        }
+        Ok(Output {
+            name: Some(name.to_owned()),
+            source,
+            geometry,
+        })
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name.as_deref().unwrap_or("*"))?;
+        if let Some(geometry) = &self.geometry {
+            write!(f, "@{}", geometry)?;
+        }
+        write!(f, "={}", self.source)
+    }
+}
+
+/// Outputs which get wallpapers.
+#[derive(Debug, Clone)]
+pub struct Outputs {
+    outputs: Vec<Output>,
+    /// One wallpaper is cropped across all outputs.
+    span: bool,
+}
+
+impl Default for Outputs {
+    /// A single wallpaper on every output.
+    fn default() -> Self {
+        Self {
+            outputs: vec![Output {
+                name: None,
+                source: OutputSource::All,
+                geometry: None,
+            }],
+            span: false,
+        }
+    }
+}
+
+impl Outputs {
+    /// Validates the outputs against the backend, detecting the layout for spanning.
+    pub fn new(outputs: Vec<Output>, span: bool, backend: &Backend) -> Result<Self> {
+        if outputs.is_empty() && !span {
+            return Ok(Self::default());
+        }
+        if !backend.supports_outputs() {
+            bail!("Setting wallpapers per output needs \"--wallpaper-command\"");
+        }
+        if !span {
+            return Ok(Self { outputs, span });
+        }
+
+        if let Some(output) = outputs
+            .iter()
+            .find(|output| output.source != OutputSource::All)
+        {
+            bail!(
+                "Spanning shows the same pollen on every output, \"{}\" can't have its own source",
+                output
+            );
+        }
+        let detected = screen::detect_outputs();
+        let outputs: Vec<Output> = if outputs.is_empty() {
+            detected
+                .into_iter()
+                .map(|(name, geometry)| Output {
+                    name: Some(name),
+                    source: OutputSource::All,
+                    geometry: Some(geometry),
+                })
+                .collect()
+        } else {
+            outputs
+                .into_iter()
+                .map(|mut output| {
+                    if output.geometry.is_none() {
+                        output.geometry = detected
+                            .iter()
+                            .find(|(name, _)| Some(name) == output.name.as_ref())
+                            .map(|(_, geometry)| *geometry);
+                    }
+                    output
+                })
+                .collect()
+        };
+        if outputs.is_empty() {
+            bail!("Couldn't detect the outputs to span, give them as \"--output <name>@<width>x<height>+<x>+<y>\"");
+        }
+        if let Some(output) = outputs.iter().find(|output| output.geometry.is_none()) {
+            bail!(
+                "Couldn't detect the geometry of output \"{}\", give it as \"--output <name>@<width>x<height>+<x>+<y>\"",
+                output
+            );
+        }
+        Ok(Self { outputs, span })
+    }
+
+    pub fn span(&self) -> bool {
+        self.span
+    }
+
+    pub fn wants(&self, source: OutputSource) -> bool {
+        self.outputs.iter().any(|output| output.source == source)
+    }
+
+    /// Outputs which show a wallpaper from `source`.
+    pub fn targets(&self, source: WallpaperSource, attach_mode: bool) -> Vec<&Output> {
+        self.outputs
+            .iter()
+            .filter(|output| output.source.accepts(source, attach_mode))
+            .collect()
+    }
+}
+
+/// Crops the wallpaper for each output so that together they show it across the layout.
+///
+/// The crops are written to `folder_path`, outputs need a geometry.
+pub async fn span(
+    input: &Path,
+    folder_path: &Path,
+    outputs: &[&Output],
+) -> Result<Vec<(Output, PathBuf)>> {
+    let input = input.to_owned();
+    let folder_path = folder_path.to_owned();
+    let outputs: Vec<Output> = outputs.iter().map(|output| (*output).clone()).collect();
+    tokio::task::spawn_blocking(move || {
+        let geometries: Vec<OutputGeometry> = outputs
+            .iter()
+            .filter_map(|output| output.geometry)
+            .collect();
+        let layout_width = geometries
+            .iter()
+            .map(|geometry| geometry.x + geometry.size.width)
+            .max()
+            .unwrap_or(0);
+        let layout_height = geometries
+            .iter()
+            .map(|geometry| geometry.y + geometry.size.height)
+            .max()
+            .unwrap_or(0);
+        if layout_width == 0 || layout_height == 0 {
+            bail!("No output geometry to span the wallpaper across");
+        }
+
+        let image = postprocess::open(&input)?.resize_to_fill(
+            layout_width,
+            layout_height,
+            FilterType::Lanczos3,
+        );
+        let stem = input
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut crops = Vec::with_capacity(outputs.len());
+        for output in outputs {
+            let geometry = match output.geometry {
+                Some(geometry) => geometry,
+                None => continue,
+            };
+            let crop = image
+                .crop_imm(
+                    geometry.x,
+                    geometry.y,
+                    geometry.size.width,
+                    geometry.size.height,
+                )
+                .to_rgb8();
+            let name = output.name.as_deref().unwrap_or("all");
+            let path = folder_path.join(format!("{}_{}.jpg", stem, sanitize(name)));
+            postprocess::save(&crop, &path)?;
+            crops.push((output, path));
+        }
+        Ok(crops)
+    })
+    .await?
+}
+
+// Output names may contain anything, keep them out of paths.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Plan;
+
+    const COMMAND: &str = "swww img -o {output} {path}";
+
+    fn parse_outputs(outputs: &[&str], span: bool, backend: &Backend) -> Result<Outputs> {
+        Outputs::new(
+            outputs
+                .iter()
+                .map(|output| output.parse())
+                .collect::<Result<_>>()?,
+            span,
+            backend,
+        )
+    }
+
+    // What would run to set a wallpaper from `source`.
+    fn plans(
+        outputs: &Outputs,
+        backend: &Backend,
+        source: WallpaperSource,
+        attach_mode: bool,
+    ) -> Vec<Plan> {
+        outputs
+            .targets(source, attach_mode)
+            .into_iter()
+            .map(|output| {
+                backend
+                    .plan(output.name.as_deref(), Path::new("/tmp/pollen.jpg"))
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn command(output: &str) -> Plan {
+        Plan::Command(format!(
+            "swww img -o {} {}",
+            crate::shell::quote(output),
+            crate::shell::quote("/tmp/pollen.jpg")
+        ))
+    }
+
+    #[test]
+    fn sets_every_output_by_default() {
+        for backend in [
+            Backend::new(false, None),
+            Backend::new(true, None),
+            Backend::new(false, Some(COMMAND)),
+            Backend::new(true, Some(COMMAND)),
+        ] {
+            let outputs = parse_outputs(&[], false, &backend).unwrap();
+            let expected = match backend.supports_outputs() {
+                true => command("*"),
+                false => Plan::System("/tmp/pollen.jpg".to_owned()),
+            };
+            assert_eq!(
+                plans(&outputs, &backend, WallpaperSource::Done, false),
+                vec![expected.clone()]
+            );
+            assert_eq!(
+                plans(&outputs, &backend, WallpaperSource::Attached, true),
+                vec![expected]
+            );
+            // Only the attached pollen in attach mode.
+            assert!(plans(&outputs, &backend, WallpaperSource::Done, true).is_empty());
+        }
+    }
+
+    #[test]
+    fn needs_command_for_outputs() {
+        for backend in [Backend::new(false, None), Backend::new(true, None)] {
+            assert!(parse_outputs(&["DP-1"], false, &backend).is_err());
+            assert!(parse_outputs(&[], true, &backend).is_err());
+        }
+    }
+
+    #[test]
+    fn sets_outputs_by_source() {
+        for backend in [
+            Backend::new(false, Some(COMMAND)),
+            Backend::new(true, Some(COMMAND)),
+        ] {
+            let outputs =
+                parse_outputs(&["DP-1=attached", "HDMI-1=done", "eDP-1"], false, &backend).unwrap();
+            assert!(outputs.wants(OutputSource::Attached));
+            assert_eq!(
+                plans(&outputs, &backend, WallpaperSource::Attached, true),
+                [command("DP-1"), command("eDP-1")]
+            );
+            assert_eq!(
+                plans(&outputs, &backend, WallpaperSource::Done, true),
+                [command("HDMI-1")]
+            );
+        }
+    }
+
+    #[test]
+    fn spans_outputs_with_geometry() {
+        let backend = Backend::new(true, Some(COMMAND));
+        let outputs = parse_outputs(
+            &["DP-1@2560x1440+0+0", "HDMI-1@1920x1080+2560+0"],
+            true,
+            &backend,
+        )
+        .unwrap();
+        assert!(outputs.span());
+        assert_eq!(
+            plans(&outputs, &backend, WallpaperSource::Done, false),
+            [command("DP-1"), command("HDMI-1")]
+        );
+        // Every output shows the same pollen when spanning.
+        assert!(parse_outputs(&["DP-1@2560x1440+0+0=attached"], true, &backend).is_err());
+    }
+}
@@ -0,0 +1,360 @@
+use crate::{
+    caption::{CaptionInfo, CaptionStyle},
+    files,
+    screen::ScreenSize,
+    shell,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use image::{
+    codecs::jpeg::JpegEncoder,
+    imageops::{self, FilterType},
+    DynamicImage, ImageFormat, ImageReader, Rgb, RgbImage,
+};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    process::Stdio,
+    str::FromStr,
+};
+use tracing::debug;
+
+const JPEG_QUALITY: u8 = 95;
+const BACKGROUND_BLUR_SIGMA: f32 = 40.0;
+
+/// How the wallpaper is placed on the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Fit {
+    /// Leave the image as it is and let the platform place it.
+    #[default]
+    None,
+    /// Scale to fit inside the screen and fill the rest with the background.
+    Contain,
+    /// Scale to cover the screen, cropping what overflows.
+    Fill,
+    /// Keep the size, scale down only if it is larger than the screen.
+    Center,
+}
+
+impl FromStr for Fit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Fit::None),
+            "fit" => Ok(Fit::Contain),
+            "fill" => Ok(Fit::Fill),
+            "center" => Ok(Fit::Center),
+            _ => bail!(
+                "Unknown fit \"{}\", expected one of \"none\", \"fit\", \"fill\" or \"center\"",
+                s
+            ),
+        }
+    }
+}
+
+/// What fills the screen around a fitted or centered wallpaper.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Background {
+    /// A blurred copy of the wallpaper covering the screen.
+    Blur,
+    Color(Rgb<u8>),
+}
+
+impl FromStr for Background {
+    type Err = anyhow::Error;
+
+    /// Parses `blur` or a color like `#1e1e2e`.
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "blur" {
+            return Ok(Background::Blur);
+        }
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        let channel = |index: usize| {
+            hex.get(index..index + 2)
+                .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+        };
+        match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Background::Color(Rgb([r, g, b]))),
+            _ => bail!(
+                "Invalid background \"{}\", expected \"blur\" or a color like \"#1e1e2e\"",
+                s
+            ),
+        }
+    }
+}
+
+/// Steps applied to a saved pollen before it is set as wallpaper.
+///
+/// The result is written to a new file, the saved pollen is left untouched.
+#[derive(Debug, Clone)]
+pub struct PostProcessing {
+    /// Scale factor for lanczos upscaling.
+    pub upscale: Option<u32>,
+    /// Command which upscales `{input}` to `{output}`, used instead of lanczos when given.
+    pub upscale_command: Option<String>,
+    pub fit: Fit,
+    pub background: Background,
+    /// Screen to fit to, detected when not given.
+    pub screen_size: Option<ScreenSize>,
+    pub caption: Option<CaptionStyle>,
+}
+
+impl PostProcessing {
+    pub fn is_enabled(&self) -> bool {
+        self.upscale.is_some()
+            || self.upscale_command.is_some()
+            || self.fit != Fit::None
+            || self.caption.is_some()
+    }
+
+    /// Processes the image at `input` into `folder_path` and returns the path of the result.
+    pub async fn apply(
+        &self,
+        input: &Path,
+        folder_path: &Path,
+        caption_info: CaptionInfo,
+    ) -> Result<PathBuf> {
+        let stem = input
+            .file_stem()
+            .ok_or_else(|| anyhow!("Invalid wallpaper path \"{}\"", input.display()))?
+            .to_string_lossy();
+        let output = folder_path.join(format!("{}_wallpaper.jpg", stem));
+        self.apply_to(input, &output, caption_info).await?;
+        Ok(output)
+    }
+
+    /// Processes the image at `input` and writes the result to `output` in the format of its extension.
+    pub async fn apply_to(
+        &self,
+        input: &Path,
+        output: &Path,
+        caption_info: CaptionInfo,
+    ) -> Result<()> {
+        let upscaled = match &self.upscale_command {
+            Some(command) => Some(upscale_with_command(command, input, output).await?),
+            None => None,
+        };
+
+        let settings = self.clone();
+        let source = upscaled.clone().unwrap_or_else(|| input.to_owned());
+        let result_path = output.to_owned();
+        let processed = tokio::task::spawn_blocking(move || {
+            let mut image = open(&source)?;
+            if settings.upscale_command.is_none() {
+                if let Some(factor) = settings.upscale.filter(|factor| *factor > 1) {
+                    image = image.resize(
+                        image.width() * factor,
+                        image.height() * factor,
+                        FilterType::Lanczos3,
+                    );
+                }
+            }
+            let mut image = settings.fit_to_screen(image);
+            if let Some(caption) = &settings.caption {
+                caption.draw(&mut image, &caption_info)?;
+            }
+            save(&image, &result_path)
+        })
+        .await?;
+
+        if let Some(upscaled) = upscaled {
+            let _ = tokio::fs::remove_file(upscaled).await;
+        }
+        processed
+    }
+
+    fn fit_to_screen(&self, image: DynamicImage) -> RgbImage {
+        let screen = match (self.fit, self.screen_size) {
+            (Fit::None, _) | (_, None) => return image.to_rgb8(),
+            (_, Some(screen)) => screen,
+        };
+        let (width, height) = (screen.width, screen.height);
+
+        let foreground = match self.fit {
+            Fit::Fill => {
+                return image
+                    .resize_to_fill(width, height, FilterType::Lanczos3)
+                    .to_rgb8()
+            }
+            Fit::Contain => image.resize(width, height, FilterType::Lanczos3),
+            Fit::Center if image.width() > width || image.height() > height => {
+                image.resize(width, height, FilterType::Lanczos3)
+            }
+            _ => image.clone(),
+        };
+
+        let mut canvas = match self.background {
+            Background::Color(color) => RgbImage::from_pixel(width, height, color),
+            Background::Blur => {
+                // Blurring a small copy is much faster and looks the same.
+                let small = image
+                    .resize_to_fill(width / 8 + 1, height / 8 + 1, FilterType::Triangle)
+                    .to_rgb8();
+                let blurred = imageops::fast_blur(&small, BACKGROUND_BLUR_SIGMA / 8.0);
+                imageops::resize(&blurred, width, height, FilterType::Triangle)
+            }
+        };
+        let x = (width - foreground.width()) / 2;
+        let y = (height - foreground.height()) / 2;
+        imageops::overlay(&mut canvas, &foreground.to_rgb8(), x.into(), y.into());
+        canvas
+    }
+}
+
+pub fn open(path: &Path) -> Result<DynamicImage> {
+    ImageReader::open(path)?
+        .with_guessed_format()?
+        .decode()
+        .with_context(|| format!("Couldn't read \"{}\"", path.display()))
+}
+
+/// Saves the image in the format of the extension of `path`.
+///
+/// It is written next to `path` first so that nothing reads a partial image.
+pub fn save(image: &RgbImage, path: &Path) -> Result<()> {
+    let format = ImageFormat::from_path(path)
+        .with_context(|| format!("Unknown image format of \"{}\"", path.display()))?;
+    if path.file_name().is_none() {
+        bail!("Invalid image path \"{}\"", path.display());
+    }
+    let partial_path = files::temp_path(path);
+    let result = write_image(image, format, &partial_path)
+        .and_then(|_| Ok(std::fs::rename(&partial_path, path)?));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&partial_path);
+    }
+    result
+}
+
+fn write_image(image: &RgbImage, format: ImageFormat, path: &Path) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    match format {
+        ImageFormat::Jpeg => {
+            JpegEncoder::new_with_quality(&mut file, JPEG_QUALITY).encode_image(image)?
+        }
+        _ => image.write_to(&mut file, format)?,
+    }
+    file.flush()?;
+    Ok(())
+}
+
+async fn upscale_with_command(command: &str, input: &Path, output: &Path) -> Result<PathBuf> {
+    let upscaled = output.with_extension("upscaled.png");
+    let command = command
+        .replace("{input}", &shell::quote(&input.to_string_lossy()))
+        .replace("{output}", &shell::quote(&upscaled.to_string_lossy()));
+    debug!(%command, "upscaling");
+    let result = shell::command(&command)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .with_context(|| format!("Couldn't run \"{}\"", command))?;
+    if !result.status.success() {
+        bail!(
+            "Upscale command failed: {}",
+            String::from_utf8_lossy(&result.stderr).trim()
+        );
+    }
+    Ok(upscaled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::TempFolder;
+
+    #[test]
+    fn parses_fits_and_backgrounds() {
+        assert_eq!("fit".parse::<Fit>().unwrap(), Fit::Contain);
+        assert_eq!("center".parse::<Fit>().unwrap(), Fit::Center);
+        assert!("stretch".parse::<Fit>().is_err());
+
+        assert_eq!("blur".parse::<Background>().unwrap(), Background::Blur);
+        assert_eq!(
+            "#1e1e2e".parse::<Background>().unwrap(),
+            Background::Color(Rgb([0x1e, 0x1e, 0x2e]))
+        );
+        assert_eq!(
+            "FF8000".parse::<Background>().unwrap(),
+            Background::Color(Rgb([255, 128, 0]))
+        );
+        for background in ["#1e1e2", "#1e1e2e0", "#1e1g2e", "#ééé", "red"] {
+            assert!(background.parse::<Background>().is_err(), "{}", background);
+        }
+    }
+
+    // Size of the result and the bounds of the white image on the black background.
+    fn place(fit: Fit, (width, height): (u32, u32), image: (u32, u32)) -> ((u32, u32), [u32; 4]) {
+        let post_processing = PostProcessing {
+            upscale: None,
+            upscale_command: None,
+            fit,
+            background: Background::Color(Rgb([0; 3])),
+            screen_size: Some(ScreenSize { width, height }),
+            caption: None,
+        };
+        let image = RgbImage::from_pixel(image.0, image.1, Rgb([255; 3]));
+        let fitted = post_processing.fit_to_screen(DynamicImage::ImageRgb8(image));
+        let white = fitted
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel.0[0] > 128)
+            .map(|(x, y, _)| (x, y));
+        let bounds = white.fold(
+            [u32::MAX, u32::MAX, 0, 0],
+            |[left, top, right, bottom], (x, y)| {
+                [left.min(x), top.min(y), right.max(x + 1), bottom.max(y + 1)]
+            },
+        );
+        (fitted.dimensions(), bounds)
+    }
+
+    #[test]
+    fn fits_to_the_screen() {
+        let landscape = (160, 90);
+        let portrait = (90, 160);
+        // Fit, screen, image, then the size and offset of the image on the screen.
+        let cases = [
+            (Fit::Contain, landscape, (90, 180), (45, 90), (57, 0)),
+            (Fit::Contain, landscape, (30, 60), (45, 90), (57, 0)),
+            (Fit::Contain, portrait, (180, 90), (90, 45), (0, 57)),
+            (Fit::Fill, landscape, (90, 180), (160, 90), (0, 0)),
+            (Fit::Fill, portrait, (180, 90), (90, 160), (0, 0)),
+            (Fit::Center, landscape, (90, 180), (45, 90), (57, 0)),
+            (Fit::Center, landscape, (30, 60), (30, 60), (65, 15)),
+            (Fit::Center, portrait, (60, 30), (60, 30), (15, 65)),
+        ];
+        for (fit, screen, image, (width, height), (x, y)) in cases {
+            assert_eq!(
+                place(fit, screen, image),
+                (screen, [x, y, x + width, y + height]),
+                "{:?} {:?} {:?}",
+                fit,
+                screen,
+                image
+            );
+        }
+        // Left as it is.
+        assert_eq!(
+            place(Fit::None, landscape, (90, 180)),
+            ((90, 180), [0, 0, 90, 180])
+        );
+    }
+
+    #[test]
+    fn saves_same_image_at_once() {
+        let folder_path = TempFolder::new("save");
+        let path = folder_path.join("wallpaper.jpg");
+        std::thread::scope(|scope| {
+            for shade in [0, 255] {
+                let path = &path;
+                scope.spawn(move || {
+                    save(&RgbImage::from_pixel(64, 64, Rgb([shade; 3])), path).unwrap()
+                });
+            }
+        });
+        assert!(open(&path).is_ok());
+        // Only the image is left.
+        assert_eq!(std::fs::read_dir(&*folder_path).unwrap().count(), 1);
+    }
+}
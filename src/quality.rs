@@ -0,0 +1,312 @@
+use crate::postprocess;
+use anyhow::{bail, Result};
+use image::imageops::FilterType;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tracing::warn;
+
+// Statistics are measured on a copy of this size.
+const SAMPLE_SIZE: u32 = 256;
+// Images whose luminance barely varies are blank.
+const BLANK_CONTRAST: f32 = 0.02;
+
+/// Statistics of the luminance of an image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageStats {
+    /// Mean luminance between 0 and 1.
+    pub brightness: f32,
+    /// Standard deviation of the luminance between 0 and 0.5.
+    pub contrast: f32,
+    /// Shannon entropy of the luminance histogram in bits between 0 and 8.
+    pub entropy: f32,
+}
+
+impl ImageStats {
+    pub fn measure(image_path: &Path) -> Result<Self> {
+        let image = postprocess::open(image_path)?
+            .resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+            .to_luma8();
+        let count = image.pixels().len() as f32;
+        if count == 0.0 {
+            bail!("\"{}\" has no pixels", image_path.display());
+        }
+
+        let mut histogram = [0_u32; 256];
+        for pixel in image.pixels() {
+            histogram[pixel.0[0] as usize] += 1;
+        }
+        let brightness = histogram
+            .iter()
+            .enumerate()
+            .map(|(value, n)| value as f32 * *n as f32)
+            .sum::<f32>()
+            / count
+            / 255.0;
+        let variance = histogram
+            .iter()
+            .enumerate()
+            .map(|(value, n)| (value as f32 / 255.0 - brightness).powi(2) * *n as f32)
+            .sum::<f32>()
+            / count;
+        let entropy = histogram
+            .iter()
+            .filter(|n| **n > 0)
+            .map(|n| {
+                let p = *n as f32 / count;
+                p * (1.0 / p).log2()
+            })
+            .sum();
+        Ok(Self {
+            brightness,
+            contrast: variance.sqrt(),
+            entropy,
+        })
+    }
+
+    /// Measures the image on the blocking pool, `None` if it can't be measured.
+    ///
+    /// Such an image isn't held back by the quality gate, the same as one which fails to post process.
+    pub async fn measure_in_background(image_path: PathBuf) -> Option<Self> {
+        let path = image_path.clone();
+        match tokio::task::spawn_blocking(move || Self::measure(&path)).await {
+            Ok(Ok(stats)) => Some(stats),
+            Ok(Err(err)) => {
+                warn!(path = %image_path.display(), error = %err, "failed to measure image, letting it pass");
+                None
+            }
+            Err(err) => {
+                warn!(path = %image_path.display(), error = %err, "measuring image stopped, letting it pass");
+                None
+            }
+        }
+    }
+}
+
+/// Why an image isn't set as wallpaper.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rejection {
+    Blank,
+    TooDark,
+    TooBright,
+    LowContrast,
+    LowEntropy,
+    /// Too bright for the night hours.
+    NotDark,
+}
+
+impl Rejection {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rejection::Blank => "blank",
+            Rejection::TooDark => "too_dark",
+            Rejection::TooBright => "too_bright",
+            Rejection::LowContrast => "low_contrast",
+            Rejection::LowEntropy => "low_entropy",
+            Rejection::NotDark => "not_dark",
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Hours of the day in local time, `20-7` is from 20:00 until 07:00.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hours {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Hours {
+    pub fn contains(&self, hour: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&hour)
+        } else {
+            // Wraps around midnight.
+            hour >= self.start || hour < self.end
+        }
+    }
+}
+
+impl FromStr for Hours {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let hours = s.split_once('-').and_then(|(start, end)| {
+            Some(Hours {
+                start: start.trim().parse().ok()?,
+                end: end.trim().parse().ok()?,
+            })
+        });
+        match hours {
+            Some(hours) if hours.start < 24 && hours.end <= 24 => Ok(hours),
+            _ => bail!(
+                "Invalid hours \"{}\", expected \"<start>-<end>\" like \"20-7\"",
+                s
+            ),
+        }
+    }
+}
+
+/// Thresholds an image has to pass to be set as wallpaper.
+#[derive(Debug, Clone, Default)]
+pub struct QualityGate {
+    pub reject_blank: bool,
+    pub min_brightness: Option<f32>,
+    pub max_brightness: Option<f32>,
+    pub min_contrast: Option<f32>,
+    pub min_entropy: Option<f32>,
+    /// Only images darker than `dark_threshold` during these hours.
+    pub dark_only: Option<Hours>,
+    pub dark_threshold: f32,
+}
+
+impl QualityGate {
+    pub fn is_enabled(&self) -> bool {
+        self.reject_blank
+            || self.min_brightness.is_some()
+            || self.max_brightness.is_some()
+            || self.min_contrast.is_some()
+            || self.min_entropy.is_some()
+            || self.dark_only.is_some()
+    }
+
+    /// Checks the image statistics at the local `hour`, `None` if the image passes.
+    pub fn check(&self, stats: &ImageStats, hour: u32) -> Option<Rejection> {
+        let below = |value: f32, threshold: Option<f32>| threshold.is_some_and(|t| value < t);
+        let above = |value: f32, threshold: Option<f32>| threshold.is_some_and(|t| value > t);
+        if self.reject_blank && stats.contrast < BLANK_CONTRAST {
+            Some(Rejection::Blank)
+        } else if below(stats.brightness, self.min_brightness) {
+            Some(Rejection::TooDark)
+        } else if above(stats.brightness, self.max_brightness) {
+            Some(Rejection::TooBright)
+        } else if below(stats.contrast, self.min_contrast) {
+            Some(Rejection::LowContrast)
+        } else if below(stats.entropy, self.min_entropy) {
+            Some(Rejection::LowEntropy)
+        } else if self.dark_only.is_some_and(|hours| hours.contains(hour))
+            && stats.brightness > self.dark_threshold
+        {
+            Some(Rejection::NotDark)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::TempFolder;
+
+    const FINE: ImageStats = ImageStats {
+        brightness: 0.4,
+        contrast: 0.2,
+        entropy: 6.0,
+    };
+
+    #[test]
+    fn parses_hours() {
+        assert_eq!(
+            "20-7".parse::<Hours>().unwrap(),
+            Hours { start: 20, end: 7 }
+        );
+        assert_eq!(
+            " 0 - 24 ".parse::<Hours>().unwrap(),
+            Hours { start: 0, end: 24 }
+        );
+        for hours in ["24-7", "20-25", "20", "20-", "-7", "8pm-7am", "-1-7"] {
+            assert!(hours.parse::<Hours>().is_err(), "{}", hours);
+        }
+    }
+
+    #[test]
+    fn contains_hours_around_midnight() {
+        let night = Hours { start: 20, end: 7 };
+        assert!(night.contains(20) && night.contains(23) && night.contains(0) && night.contains(3));
+        assert!(!night.contains(7) && !night.contains(12) && !night.contains(19));
+        let day = Hours { start: 8, end: 18 };
+        assert!(day.contains(8) && day.contains(12));
+        assert!(!day.contains(18) && !day.contains(3));
+    }
+
+    #[test]
+    fn checks_thresholds_in_order() {
+        let gate = QualityGate {
+            reject_blank: true,
+            min_brightness: Some(0.1),
+            max_brightness: Some(0.9),
+            min_contrast: Some(0.1),
+            min_entropy: Some(4.0),
+            dark_only: Some(Hours { start: 20, end: 7 }),
+            dark_threshold: 0.3,
+        };
+        let check = |stats: ImageStats, hour| gate.check(&stats, hour);
+        assert_eq!(check(FINE, 12), None);
+        // A blank image is only reported as blank, dark as it may be.
+        let blank = ImageStats {
+            brightness: 0.0,
+            contrast: 0.0,
+            entropy: 0.0,
+        };
+        assert_eq!(check(blank, 12), Some(Rejection::Blank));
+        let dark = ImageStats {
+            brightness: 0.05,
+            contrast: 0.05,
+            ..FINE
+        };
+        assert_eq!(check(dark, 12), Some(Rejection::TooDark));
+        let bright = ImageStats {
+            brightness: 0.95,
+            ..FINE
+        };
+        assert_eq!(check(bright, 12), Some(Rejection::TooBright));
+        let flat = ImageStats {
+            contrast: 0.05,
+            entropy: 2.0,
+            ..FINE
+        };
+        assert_eq!(check(flat, 12), Some(Rejection::LowContrast));
+        let plain = ImageStats {
+            entropy: 2.0,
+            ..FINE
+        };
+        assert_eq!(check(plain, 12), Some(Rejection::LowEntropy));
+        assert_eq!(check(FINE, 23), Some(Rejection::NotDark));
+        assert_eq!(check(FINE, 3), Some(Rejection::NotDark));
+        // Thresholds are exclusive.
+        let night = ImageStats {
+            brightness: 0.3,
+            ..FINE
+        };
+        assert_eq!(check(night, 23), None);
+    }
+
+    #[test]
+    fn lets_everything_pass_when_disabled() {
+        let gate = QualityGate::default();
+        assert!(!gate.is_enabled());
+        let blank = ImageStats {
+            brightness: 1.0,
+            contrast: 0.0,
+            entropy: 0.0,
+        };
+        assert_eq!(gate.check(&blank, 23), None);
+    }
+
+    #[tokio::test]
+    async fn lets_corrupt_images_pass() {
+        let folder_path = TempFolder::new("corrupt");
+        let path = folder_path.join("corrupt.jpg");
+        std::fs::write(&path, b"\xff\xd8\xff\xe0 not really a jpeg").unwrap();
+        assert!(ImageStats::measure(&path).is_err());
+        assert_eq!(ImageStats::measure_in_background(path).await, None);
+    }
+}
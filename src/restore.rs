@@ -0,0 +1,37 @@
+use crate::{backend::Backend, files};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+const PREVIOUS_WALLPAPER_FILE_NAME: &str = "previous_wallpaper";
+
+/// Records the wallpaper which was set before `pollenwall` started and returns it.
+///
+/// A pollen from the app folder is left over from a run which didn't exit cleanly,
+/// the wallpaper recorded by that run is returned instead.
+pub fn record(app_folder_path: &Path, backend: &Backend) -> Result<Option<PathBuf>> {
+    let path = app_folder_path.join(PREVIOUS_WALLPAPER_FILE_NAME);
+    let current = backend.get()?;
+    match current {
+        Some(current) if !current.starts_with(app_folder_path) => {
+            files::write_atomic(&path, current.to_string_lossy().as_bytes())?;
+            Ok(Some(current))
+        }
+        _ => Ok(std::fs::read_to_string(&path)
+            .ok()
+            .map(|recorded| PathBuf::from(recorded.trim()))),
+    }
+}
+
+/// Sets the recorded wallpaper back and forgets it.
+pub async fn restore(app_folder_path: &Path, backend: &Backend, previous: &Path) {
+    match backend.set(None, previous).await {
+        Ok(()) => {
+            info!(path = %previous.display(), "restored previous wallpaper");
+            let _ = std::fs::remove_file(app_folder_path.join(PREVIOUS_WALLPAPER_FILE_NAME));
+        }
+        Err(err) => {
+            warn!(path = %previous.display(), error = %err, "failed to restore previous wallpaper")
+        }
+    }
+}
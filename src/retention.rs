@@ -0,0 +1,140 @@
+use crate::{cache::Cache, history::History};
+use anyhow::Result;
+use std::{
+    io::ErrorKind,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+/// How many of the wallpapers set so far keep their files on disk.
+///
+/// Limits count from the newest wallpaper, a wallpaper is kept while it is within all of them.
+/// The newest wallpaper and favourites are always kept, favourites don't count towards the limits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Retention {
+    pub keep_last: Option<usize>,
+    /// Bytes of the kept files together.
+    pub max_size: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+impl Default for Retention {
+    /// Only the current wallpaper.
+    fn default() -> Self {
+        Self {
+            keep_last: Some(1),
+            max_size: None,
+            max_age: None,
+        }
+    }
+}
+
+impl Retention {
+    /// Whether the `count`th newest wallpaper is kept, `size` is its size together with the newer ones.
+    pub fn keeps(&self, count: usize, size: u64, age: Duration) -> bool {
+        count == 1
+            || (self.keep_last.is_none_or(|keep_last| count <= keep_last)
+                && self.max_size.is_none_or(|max_size| size <= max_size)
+                && self.max_age.is_none_or(|max_age| age <= max_age))
+    }
+}
+
+/// Removes the files which the retention doesn't keep and evicts the cache, for `pollenwall gc`.
+pub fn collect(
+    app_folder_path: &Path,
+    retention: &Retention,
+    cache_size: u64,
+    dry_run: bool,
+) -> Result<()> {
+    let mut history = History::load(app_folder_path);
+    let mut cache = Cache::load(app_folder_path, cache_size);
+    let mut expired = history.expire(retention, SystemTime::now());
+    // Cached evolutions stay until the cache evicts them.
+    expired.retain(|path| !cache.contains(path));
+    expired.extend(cache.evict(|path| history.has_file(path)));
+    let mut size = 0;
+    for path in &expired {
+        size += std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+        if dry_run {
+            println!("would remove {}", path.display());
+            continue;
+        }
+        match std::fs::remove_file(path) {
+            Ok(()) => println!("removed {}", path.display()),
+            // Removed by hand or by the running instance.
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => println!("couldn't remove {}: {}", path.display(), err),
+        }
+    }
+    if !dry_run {
+        history.save()?;
+        cache.save()?;
+    }
+    println!(
+        "{} {} files, {:.1} MB",
+        if dry_run { "would remove" } else { "removed" },
+        expired.len(),
+        size as f64 / 1_000_000.0
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{files::TempFolder, history::HistoryEntry};
+
+    #[test]
+    fn keeps_wallpapers_within_all_limits() {
+        let retention = Retention {
+            keep_last: Some(3),
+            max_size: Some(100),
+            max_age: Some(Duration::from_secs(60)),
+        };
+        assert!(retention.keeps(3, 100, Duration::from_secs(60)));
+        assert!(!retention.keeps(4, 100, Duration::from_secs(60)));
+        assert!(!retention.keeps(2, 101, Duration::from_secs(60)));
+        assert!(!retention.keeps(2, 100, Duration::from_secs(61)));
+        // The newest wallpaper is kept whatever the limits.
+        assert!(retention.keeps(1, 1000, Duration::from_secs(1000)));
+        assert!(Retention::default().keeps(1, 0, Duration::ZERO));
+        assert!(!Retention::default().keeps(2, 0, Duration::ZERO));
+    }
+
+    #[test]
+    fn removes_only_expired_files_in_app_folder() {
+        let app_folder_path = TempFolder::new("collect");
+        let outside_folder_path = TempFolder::new("outside");
+        let outside = outside_folder_path.join("outside.jpg");
+        let old = app_folder_path.join("old.jpg");
+        let new = app_folder_path.join("new.jpg");
+        for path in [&outside, &old, &new] {
+            std::fs::write(path, b"pollen").unwrap();
+        }
+        let mut history = History::load(&app_folder_path);
+        for (path, files) in [
+            (&old, vec![old.clone(), outside.clone()]),
+            (&new, vec![new.clone()]),
+        ] {
+            history.push(HistoryEntry {
+                pollen_uuid: "uuid".to_owned(),
+                cid: "cid".to_owned(),
+                evolution: "evolution.jpg".to_owned(),
+                path: path.clone(),
+                prompt: None,
+                model: None,
+                set_at: 0,
+                favourite: false,
+                files,
+            });
+        }
+        history.save().unwrap();
+
+        collect(&app_folder_path, &Retention::default(), 0, true).unwrap();
+        assert!(old.exists());
+        collect(&app_folder_path, &Retention::default(), 0, false).unwrap();
+        assert!(!old.exists());
+        assert!(new.exists());
+        assert!(outside.exists());
+    }
+}
@@ -0,0 +1,255 @@
+use anyhow::{bail, Result};
+use std::{fmt, process::Command, str::FromStr};
+use tracing::debug;
+
+/// Resolution of a screen in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScreenSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for ScreenSize {
+    type Err = anyhow::Error;
+
+    /// Parses `<width>x<height>`, for example `2560x1440`.
+    fn from_str(s: &str) -> Result<Self> {
+        let parsed = s.split_once(['x', 'X']).and_then(|(width, height)| {
+            Some(ScreenSize {
+                width: width.trim().parse().ok()?,
+                height: height.trim().parse().ok()?,
+            })
+        });
+        match parsed {
+            Some(size) if size.width > 0 && size.height > 0 => Ok(size),
+            _ => bail!(
+                "Invalid screen size \"{}\", expected \"<width>x<height>\"",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ScreenSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// Size and position of an output in the screen layout, the top left corner is `0,0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputGeometry {
+    pub size: ScreenSize,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl FromStr for OutputGeometry {
+    type Err = anyhow::Error;
+
+    /// Parses `<width>x<height>+<x>+<y>`, for example `2560x1440+1920+0`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split('+');
+        let size = parts.next().unwrap_or_default().parse::<ScreenSize>();
+        let position: Vec<Option<u32>> = parts.map(|part| part.trim().parse().ok()).collect();
+        match (size, position.as_slice()) {
+            (Ok(size), [Some(x), Some(y)]) => Ok(OutputGeometry { size, x: *x, y: *y }),
+            _ => bail!(
+                "Invalid output geometry \"{}\", expected \"<width>x<height>+<x>+<y>\"",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for OutputGeometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{}+{}", self.size, self.x, self.y)
+    }
+}
+
+/// Asks the platform for the connected outputs and their geometries.
+///
+/// Only Linux with `xrandr` is supported, other platforms have no outputs.
+pub fn detect_outputs() -> Vec<(String, OutputGeometry)> {
+    let outputs = detect_outputs_with_platform_tool();
+    debug!(outputs = ?outputs.iter().map(|(name, geometry)| format!("{}@{}", name, geometry)).collect::<Vec<_>>(), "detected outputs");
+    outputs
+}
+
+#[cfg(target_os = "linux")]
+fn detect_outputs_with_platform_tool() -> Vec<(String, OutputGeometry)> {
+    match run("xrandr", &["--listmonitors"]) {
+        Some(output) => parse_monitors(&output)
+            .into_iter()
+            .map(|monitor| (monitor.name, monitor.geometry))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// A monitor listed by `xrandr --listmonitors`.
+#[cfg(any(target_os = "linux", test))]
+#[derive(Debug, PartialEq)]
+struct Monitor {
+    name: String,
+    geometry: OutputGeometry,
+    primary: bool,
+}
+
+#[cfg(any(target_os = "linux", test))]
+fn parse_monitors(output: &str) -> Vec<Monitor> {
+    // Example line ` 0: +*DP-1 2560/597x1440/336+0+0  DP-1`, `*` marks the primary monitor.
+    output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut words = line.split_whitespace().skip(1);
+            let primary = words.next()?.contains('*');
+            let geometry = words.next()?;
+            let name = words.next()?;
+            // Drop the physical sizes, `2560/597x1440/336+0+0` becomes `2560x1440+0+0`.
+            let (width, rest) = geometry.split_once('x')?;
+            let width = width.split('/').next()?;
+            let (height, position) = rest.split_once('+')?;
+            let height = height.split('/').next()?;
+            let geometry = format!("{}x{}+{}", width, height, position).parse().ok()?;
+            Some(Monitor {
+                name: name.to_owned(),
+                geometry,
+                primary,
+            })
+        })
+        .collect()
+}
+
+/// Size of the primary monitor, the first one if none is marked primary.
+#[cfg(any(target_os = "linux", test))]
+fn primary_size(monitors: &[Monitor]) -> Option<ScreenSize> {
+    monitors
+        .iter()
+        .find(|monitor| monitor.primary)
+        .or_else(|| monitors.first())
+        .map(|monitor| monitor.geometry.size)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn detect_outputs_with_platform_tool() -> Vec<(String, OutputGeometry)> {
+    Vec::new()
+}
+
+/// Asks the platform for the resolution of the primary screen.
+pub fn detect() -> Option<ScreenSize> {
+    let size = detect_with_platform_tool();
+    debug!(size = ?size.map(|size| size.to_string()), "detected screen size");
+    size
+}
+
+#[cfg(target_os = "linux")]
+fn detect_with_platform_tool() -> Option<ScreenSize> {
+    // Works on X11 and on Wayland through XWayland.
+    // The screen of `xrandr --current` spans all monitors, the primary one is what a wallpaper is made for.
+    let output = run("xrandr", &["--listmonitors"])?;
+    primary_size(&parse_monitors(&output))
+}
+
+#[cfg(target_os = "macos")]
+fn detect_with_platform_tool() -> Option<ScreenSize> {
+    // Example line `Resolution: 2880 x 1800 Retina`
+    let output = run("system_profiler", &["SPDisplaysDataType"])?;
+    let resolution = output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Resolution:"))?;
+    let mut numbers = resolution
+        .split_whitespace()
+        .filter_map(|word| word.parse::<u32>().ok());
+    Some(ScreenSize {
+        width: numbers.next()?,
+        height: numbers.next()?,
+    })
+}
+
+#[cfg(target_os = "windows")]
+fn detect_with_platform_tool() -> Option<ScreenSize> {
+    let output = run(
+        "wmic",
+        &[
+            "path",
+            "Win32_VideoController",
+            "get",
+            "CurrentHorizontalResolution,CurrentVerticalResolution",
+        ],
+    )?;
+    let mut numbers = output
+        .split_whitespace()
+        .filter_map(|word| word.parse::<u32>().ok());
+    Some(ScreenSize {
+        width: numbers.next()?,
+        height: numbers.next()?,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn detect_with_platform_tool() -> Option<ScreenSize> {
+    None
+}
+
+#[allow(dead_code)]
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TWO_MONITORS: &str = "Monitors: 2
+ 0: +HDMI-1 1920/531x1080/299+0+0  HDMI-1
+ 1: +*DP-1 2560/597x1440/336+1920+0  DP-1
+";
+
+    #[test]
+    fn parses_monitors() {
+        let monitors = parse_monitors(TWO_MONITORS);
+        assert_eq!(
+            monitors,
+            [
+                Monitor {
+                    name: "HDMI-1".to_owned(),
+                    geometry: "1920x1080+0+0".parse().unwrap(),
+                    primary: false,
+                },
+                Monitor {
+                    name: "DP-1".to_owned(),
+                    geometry: "2560x1440+1920+0".parse().unwrap(),
+                    primary: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn sizes_screen_by_primary_monitor() {
+        assert_eq!(
+            primary_size(&parse_monitors(TWO_MONITORS)),
+            Some(ScreenSize {
+                width: 2560,
+                height: 1440
+            })
+        );
+        // Without a primary monitor the first one is taken.
+        let no_primary = TWO_MONITORS.replace('*', "");
+        assert_eq!(
+            primary_size(&parse_monitors(&no_primary)),
+            Some(ScreenSize {
+                width: 1920,
+                height: 1080
+            })
+        );
+        assert_eq!(primary_size(&parse_monitors("Monitors: 0\n")), None);
+    }
+}
@@ -0,0 +1,242 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+pub const SERVICE_FILE_NAME: &str = "pollenwall.service";
+// Pollenwall stops notifying the watchdog once heartbeats are missing for
+// longer than the heartbeat timeout, systemd restarts it after this many seconds more.
+const WATCHDOG_SEC: u64 = 60;
+// Setting the wallpaper needs to reach the desktop session, which the user manager doesn't always know of.
+const SESSION_VARIABLES: [&str; 3] = ["DISPLAY", "WAYLAND_DISPLAY", "DBUS_SESSION_BUS_ADDRESS"];
+
+/// What `pollenwall service` does.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Install with the options to run with.
+    Install(Vec<String>),
+    Uninstall,
+    Status,
+}
+
+/// Splits the options given to `--generate-service`, quoted as in a shell.
+pub fn parse_args(args: &str) -> Result<Vec<String>> {
+    shell_words::split(args).with_context(|| format!("Invalid service arguments \"{}\"", args))
+}
+
+/// A systemd user unit which runs `executable` with `args`.
+///
+/// `environment` are the variables of the desktop session, see [`session_environment`].
+pub fn unit(executable: &Path, args: &[String], environment: &[(&str, String)]) -> String {
+    let exec_start = std::iter::once(executable.to_string_lossy().as_ref())
+        .chain(args.iter().map(String::as_str))
+        // Systemd expands variables in command lines.
+        .map(|word| quote(&word.replace('$', "$$")))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut unit = format!(
+        "[Unit]\n\
+         Description=Sets your wallpaper with pollens incoming from pollinations.ai\n\
+         PartOf=graphical-session.target\n\
+         After=graphical-session.target\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         NotifyAccess=main\n\
+         ExecStart={}\n\
+         ExecReload=/bin/kill -HUP $MAINPID\n\
+         Restart=on-failure\n\
+         RestartSec=5\n\
+         WatchdogSec={}\n\
+         SyslogIdentifier=pollenwall\n",
+        exec_start, WATCHDOG_SEC
+    );
+    for (name, value) in environment {
+        unit.push_str(&format!(
+            "Environment={}\n",
+            quote(&format!("{}={}", name, value))
+        ));
+    }
+    // Started and stopped with the desktop session, which has the display to set the wallpaper on.
+    unit.push_str("\n[Install]\nWantedBy=graphical-session.target\n");
+    unit
+}
+
+/// Quotes a word of a unit file setting if it needs to, `%` specifiers are escaped either way.
+fn quote(word: &str) -> String {
+    let word = word.replace('%', "%%");
+    let plain = !word.is_empty()
+        && !word
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';'));
+    if plain {
+        return word;
+    }
+    let escaped = word
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
+
+/// Variables of the desktop session which are set now.
+pub fn session_environment() -> Vec<(&'static str, String)> {
+    SESSION_VARIABLES
+        .iter()
+        .filter_map(|name| {
+            std::env::var(name)
+                .ok()
+                .filter(|value| !value.is_empty())
+                .map(|value| (*name, value))
+        })
+        .collect()
+}
+
+/// Where systemd looks for the units of the user.
+pub fn folder_path(home: &Path) -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| home.join(".config"))
+        .join("systemd")
+        .join("user")
+}
+
+pub fn run(action: &Action, home: &Path) -> Result<()> {
+    let service_path = folder_path(home).join(SERVICE_FILE_NAME);
+    match action {
+        Action::Install(args) => {
+            let executable_path = std::env::current_exe()
+                .context("Couldn't get current executable path, please try again.")?;
+            std::fs::create_dir_all(folder_path(home))?;
+            std::fs::write(
+                &service_path,
+                unit(&executable_path, args, &session_environment()),
+            )
+            .with_context(|| format!("Couldn't write \"{}\"", service_path.display()))?;
+            systemctl(&["daemon-reload"])?;
+            systemctl(&["enable", SERVICE_FILE_NAME])?;
+            // Picks up the new unit if it was running already.
+            systemctl(&["restart", SERVICE_FILE_NAME])?;
+            println!(
+                "Installed and started \"{}\", it starts with your desktop session.",
+                service_path.display()
+            );
+        }
+        Action::Uninstall => {
+            if !service_path.exists() {
+                println!("\"pollenwall\" service isn't installed.");
+                return Ok(());
+            }
+            systemctl(&["disable", "--now", SERVICE_FILE_NAME])?;
+            match std::fs::remove_file(&service_path) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("Couldn't remove \"{}\"", service_path.display()))
+                }
+            }
+            systemctl(&["daemon-reload"])?;
+            println!("Uninstalled \"{}\".", service_path.display());
+        }
+        Action::Status => {
+            if !service_path.exists() {
+                println!("\"pollenwall\" service isn't installed.");
+                return Ok(());
+            }
+            // Exits with a failure when the service isn't running, which is an answer too.
+            Command::new("systemctl")
+                .args(["--user", "--no-pager", "status", SERVICE_FILE_NAME])
+                .status()
+                .context("Couldn't run \"systemctl\"")?;
+        }
+    }
+    Ok(())
+}
+
+fn systemctl(args: &[&str]) -> Result<()> {
+    let status = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .status()
+        .context("Couldn't run \"systemctl\"")?;
+    if !status.success() {
+        bail!("\"systemctl --user {}\" failed, {}", args.join(" "), status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_unit() {
+        let args = [
+            "--attach".to_owned(),
+            "--keep-last".to_owned(),
+            "5".to_owned(),
+        ];
+        let environment = [
+            ("DISPLAY", ":0".to_owned()),
+            ("WAYLAND_DISPLAY", "wayland-0".to_owned()),
+            (
+                "DBUS_SESSION_BUS_ADDRESS",
+                "unix:path=/run/user/1000/bus".to_owned(),
+            ),
+        ];
+        assert_eq!(
+            unit(Path::new("/usr/bin/pollenwall"), &args, &environment),
+            include_str!("../tests/golden/pollenwall.service")
+        );
+    }
+
+    #[test]
+    fn quotes_args_and_environment() {
+        let args = parse_args(
+            r##"--attach-to "prompt:a \"blue\" sky" --theme-hook 'notify-send "$USER" 100%' --background "#1e1e2e" ";" "" 'back\slash'"##,
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "--attach-to",
+                r#"prompt:a "blue" sky"#,
+                "--theme-hook",
+                r#"notify-send "$USER" 100%"#,
+                "--background",
+                "#1e1e2e",
+                ";",
+                "",
+                r"back\slash",
+            ]
+        );
+        let environment = [
+            ("DISPLAY", ":0".to_owned()),
+            ("NAME", "two words".to_owned()),
+        ];
+        assert_eq!(
+            unit(
+                Path::new("/home/jane doe/bin/pollenwall"),
+                &args,
+                &environment
+            ),
+            include_str!("../tests/golden/pollenwall_quoted.service")
+        );
+    }
+
+    #[test]
+    fn rejects_unbalanced_quotes() {
+        assert!(parse_args(r#"--attach-to "prompt:sky"#).is_err());
+    }
+
+    #[test]
+    fn generates_unit_without_args_or_environment() {
+        assert_eq!(
+            unit(Path::new("/usr/bin/pollenwall"), &[], &[]),
+            include_str!("../tests/golden/pollenwall_minimal.service")
+        );
+    }
+}
@@ -0,0 +1,25 @@
+/// Runs a user given command line through the platform shell.
+#[cfg(unix)]
+pub fn command(command: &str) -> tokio::process::Command {
+    let mut shell = tokio::process::Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+pub fn command(command: &str) -> tokio::process::Command {
+    let mut shell = tokio::process::Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// Quotes an argument to be placed in a command line for the platform shell.
+#[cfg(unix)]
+pub fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(windows)]
+pub fn quote(s: &str) -> String {
+    format!("\"{}\"", s)
+}
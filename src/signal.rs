@@ -0,0 +1,55 @@
+use anyhow::Result;
+
+/// A signal which `pollenwall` acts on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Received {
+    /// Quit, with the name of the signal.
+    Shutdown(&'static str),
+    /// Read the config again.
+    Reload,
+}
+
+/// Signals which control `pollenwall`, listened to from the start so that none is missed.
+pub struct Signals {
+    #[cfg(unix)]
+    interrupt: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    hangup: tokio::signal::unix::Signal,
+}
+
+impl Signals {
+    pub fn new() -> Result<Self> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            Ok(Self {
+                interrupt: signal(SignalKind::interrupt())?,
+                terminate: signal(SignalKind::terminate())?,
+                hangup: signal(SignalKind::hangup())?,
+            })
+        }
+        #[cfg(not(unix))]
+        {
+            Ok(Self {})
+        }
+    }
+
+    /// Waits for the next signal.
+    pub async fn recv(&mut self) -> Received {
+        #[cfg(unix)]
+        {
+            tokio::select! {
+                _ = self.interrupt.recv() => Received::Shutdown("SIGINT"),
+                _ = self.terminate.recv() => Received::Shutdown("SIGTERM"),
+                _ = self.hangup.recv() => Received::Reload,
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = tokio::signal::ctrl_c().await;
+            Received::Shutdown("Ctrl-C")
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use crate::files;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tracing::warn;
+
+const STATE_FILE_NAME: &str = "state.json";
+
+/// Snapshot of the pollens which are tracked, restored when `pollenwall` starts again.
+///
+/// Times are seconds since unix epoch.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    pub saved_at: u64,
+    /// Processing pollens.
+    pub pollens: Vec<PollenState>,
+    pub attached_pollen: Option<String>,
+    #[serde(default)]
+    pub skipped: Vec<String>,
+    /// Done pollens which were set as wallpaper, newest first.
+    #[serde(default)]
+    pub done: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PollenState {
+    pub uuid: String,
+    pub model: Option<String>,
+    pub text_input: Option<String>,
+    pub hash: String,
+    pub evolution: Option<EvolutionState>,
+    pub last_set_evolution: Option<String>,
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+/// The latest evolution of a pollen.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EvolutionState {
+    pub hash: String,
+    pub name: String,
+    pub size: u64,
+}
+
+impl State {
+    /// Loads the state from the app folder, a missing or unreadable file is an empty state.
+    pub fn load(app_folder_path: &Path) -> Self {
+        let path = app_folder_path.join(STATE_FILE_NAME);
+        match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|err| {
+                warn!(path = %path.display(), error = %err, "couldn't parse state, starting afresh");
+                State::default()
+            }),
+            Err(_) => State::default(),
+        }
+    }
+
+    pub fn write(&self, app_folder_path: &Path) -> Result<()> {
+        let path = app_folder_path.join(STATE_FILE_NAME);
+        files::write_atomic(&path, serde_json::to_vec(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::TempFolder;
+
+    #[test]
+    fn starts_afresh_without_a_readable_state() {
+        let folder = TempFolder::new("state");
+        assert!(State::load(&folder).pollens.is_empty());
+        std::fs::write(folder.join(STATE_FILE_NAME), "{").unwrap();
+        assert!(State::load(&folder).pollens.is_empty());
+    }
+
+    #[test]
+    fn loads_states_written_before_skips_were_kept() {
+        let folder = TempFolder::new("state");
+        std::fs::write(
+            folder.join(STATE_FILE_NAME),
+            r#"{"saved_at":1,"pollens":[],"attached_pollen":"uuid"}"#,
+        )
+        .unwrap();
+        let state = State::load(&folder);
+        assert_eq!(state.attached_pollen.as_deref(), Some("uuid"));
+        assert!(state.skipped.is_empty() && state.done.is_empty());
+    }
+}
@@ -0,0 +1,100 @@
+use crate::files;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const STATUS_FILE_NAME: &str = "status.json";
+
+/// Snapshot of a running `pollenwall` which `pollenwall status` reads.
+///
+/// Times are seconds since unix epoch.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    pub pid: u32,
+    pub updated_at: u64,
+    pub last_heartbeat_at: Option<u64>,
+    pub heartbeat_timeout: u64,
+    pub heartbeat_stale: bool,
+    pub processing_pollens: usize,
+    pub attached_pollen: Option<String>,
+    #[serde(default)]
+    pub attach_selector: Option<String>,
+    #[serde(default)]
+    pub processing: Vec<StatusPollen>,
+}
+
+/// A processing pollen listed in the status.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusPollen {
+    pub uuid: String,
+    pub model: Option<String>,
+    pub prompt: Option<String>,
+}
+
+pub fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn ago(then: u64) -> String {
+    let secs = unix_secs(SystemTime::now()).saturating_sub(then);
+    format!("{}s ago", secs)
+}
+
+impl Status {
+    pub fn write(&self, app_folder_path: &Path) -> Result<()> {
+        let path = app_folder_path.join(STATUS_FILE_NAME);
+        // Write to a temporary file first so readers never see a half written status.
+        files::write_atomic(&path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn read(app_folder_path: &Path) -> Result<Self> {
+        let path = app_folder_path.join(STATUS_FILE_NAME);
+        let contents = std::fs::read(&path).with_context(|| {
+            format!(
+                "Couldn't read \"{}\", is \"pollenwall\" running?",
+                path.display()
+            )
+        })?;
+        Ok(serde_json::from_slice(&contents)?)
+    }
+
+    /// Prints the status in a human readable form.
+    ///
+    /// `refresh_interval` is how often a running instance updates the status,
+    /// a status older than a few intervals most likely belongs to a stopped instance.
+    pub fn print(&self, refresh_interval: Duration) {
+        let now = unix_secs(SystemTime::now());
+        let outdated = now.saturating_sub(self.updated_at) > 3 * refresh_interval.as_secs();
+
+        println!(
+            "pid:                {}{}",
+            self.pid,
+            if outdated { " (not running?)" } else { "" }
+        );
+        println!("updated:            {}", ago(self.updated_at));
+        println!(
+            "last heartbeat:     {}{}",
+            self.last_heartbeat_at
+                .map_or_else(|| "never".to_string(), ago),
+            if self.heartbeat_stale {
+                format!(" (stale, timeout is {}s)", self.heartbeat_timeout)
+            } else {
+                String::new()
+            }
+        );
+        println!("processing pollens: {}", self.processing_pollens);
+        println!(
+            "attached pollen:    {}",
+            self.attached_pollen.as_deref().unwrap_or("none")
+        );
+        if let Some(attach_selector) = &self.attach_selector {
+            println!("attach selector:    {}", attach_selector);
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use crate::{files, postprocess, shell};
+use anyhow::{bail, Context, Result};
+use image::{imageops::FilterType, Rgb};
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use tracing::{debug, info};
+
+pub const THEME_FOLDER_NAME: &str = "theme";
+// Pixels are sampled from a copy of this size.
+const SAMPLE_SIZE: u32 = 128;
+// Dominant colors which the 16 terminal colors are made of.
+const DOMINANT_COLORS: usize = 8;
+
+/// Terminal colors in the order of pywal, `colors[0]` is the background and `colors[15]` the foreground.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub background: Rgb<u8>,
+    pub foreground: Rgb<u8>,
+    pub colors: [Rgb<u8>; 16],
+}
+
+impl Palette {
+    /// Dominant colors of the image, found by median cut.
+    pub fn extract(image_path: &Path) -> Result<Self> {
+        let image = postprocess::open(image_path)?
+            .resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+            .to_rgb8();
+        let pixels: Vec<[u8; 3]> = image.pixels().map(|pixel| pixel.0).collect();
+        if pixels.is_empty() {
+            bail!("\"{}\" has no pixels", image_path.display());
+        }
+        let mut dominant = median_cut(pixels, DOMINANT_COLORS);
+        dominant.sort_by(|a, b| luminance(*a).total_cmp(&luminance(*b)));
+        // Blank images have fewer colors, repeat the brightest.
+        while dominant.len() < DOMINANT_COLORS {
+            dominant.push(*dominant.last().unwrap());
+        }
+
+        // Darkest and brightest pushed towards black and white for readable text.
+        let background = mix(dominant[0], Rgb([0, 0, 0]), 0.5);
+        let foreground = mix(dominant[DOMINANT_COLORS - 1], Rgb([255, 255, 255]), 0.5);
+        let mut colors = [background; 16];
+        colors[1..7].copy_from_slice(&dominant[1..7]);
+        colors[7] = mix(foreground, background, 0.2);
+        colors[8] = mix(background, foreground, 0.25);
+        colors[9..15].copy_from_slice(&dominant[1..7]);
+        colors[15] = foreground;
+        Ok(Self {
+            background,
+            foreground,
+            colors,
+        })
+    }
+
+    /// Same as the `colors.json` of pywal.
+    pub fn to_pywal_json(&self, wallpaper_path: &Path) -> Result<String> {
+        #[derive(Serialize)]
+        struct Special {
+            background: String,
+            foreground: String,
+            cursor: String,
+        }
+        // Keeps `color0` to `color15` in order.
+        struct Colors<'a>(&'a [Rgb<u8>; 16]);
+        impl Serialize for Colors<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let mut map = serializer.serialize_map(Some(self.0.len()))?;
+                for (index, color) in self.0.iter().enumerate() {
+                    map.serialize_entry(&format!("color{}", index), &hex(*color))?;
+                }
+                map.end()
+            }
+        }
+        #[derive(Serialize)]
+        struct PywalColors<'a> {
+            wallpaper: String,
+            alpha: String,
+            special: Special,
+            colors: Colors<'a>,
+        }
+        let colors = PywalColors {
+            wallpaper: wallpaper_path.to_string_lossy().into_owned(),
+            alpha: "100".to_owned(),
+            special: Special {
+                background: hex(self.background),
+                foreground: hex(self.foreground),
+                cursor: hex(self.foreground),
+            },
+            colors: Colors(&self.colors),
+        };
+        Ok(serde_json::to_string_pretty(&colors)?)
+    }
+
+    pub fn to_xresources(&self) -> String {
+        let mut xresources = String::new();
+        let _ = writeln!(xresources, "*.foreground: {}", hex(self.foreground));
+        let _ = writeln!(xresources, "*.background: {}", hex(self.background));
+        let _ = writeln!(xresources, "*.cursorColor: {}", hex(self.foreground));
+        for (index, color) in self.colors.iter().enumerate() {
+            let _ = writeln!(xresources, "*.color{}: {}", index, hex(*color));
+        }
+        xresources
+    }
+
+    pub fn to_css(&self, wallpaper_path: &Path) -> String {
+        let mut css = String::from(":root {\n");
+        let _ = writeln!(
+            css,
+            "  --wallpaper: url(\"{}\");",
+            wallpaper_path.to_string_lossy().replace('"', "\\\"")
+        );
+        let _ = writeln!(css, "  --background: {};", hex(self.background));
+        let _ = writeln!(css, "  --foreground: {};", hex(self.foreground));
+        let _ = writeln!(css, "  --cursor: {};", hex(self.foreground));
+        for (index, color) in self.colors.iter().enumerate() {
+            let _ = writeln!(css, "  --color{}: {};", index, hex(*color));
+        }
+        css.push_str("}\n");
+        css
+    }
+}
+
+/// Theme files which follow the wallpaper.
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub folder_path: PathBuf,
+    /// Command which is run after the files are written, `{path}` is replaced with the folder path.
+    pub hook: Option<String>,
+}
+
+impl Theme {
+    /// Exports the palette of the pollen, `wallpaper_path` is what is written as the wallpaper in the files.
+    pub async fn update(&self, pollen_path: &Path, wallpaper_path: &Path) -> Result<()> {
+        let pollen_path = pollen_path.to_owned();
+        let palette = tokio::task::spawn_blocking(move || Palette::extract(&pollen_path)).await??;
+
+        tokio::fs::create_dir_all(&self.folder_path).await?;
+        let theme_files = [
+            ("colors.json", palette.to_pywal_json(wallpaper_path)?),
+            ("colors.Xresources", palette.to_xresources()),
+            ("colors.css", palette.to_css(wallpaper_path)),
+        ];
+        for (name, contents) in theme_files {
+            // Nothing reads a partial theme.
+            files::write_atomic(&self.folder_path.join(name), contents)?;
+        }
+        info!(path = %self.folder_path.display(), background = %hex(palette.background), "theme updated");
+
+        if let Some(hook) = &self.hook {
+            let command =
+                hook.replace("{path}", &shell::quote(&self.folder_path.to_string_lossy()));
+            debug!(%command, "running theme hook");
+            let output = shell::command(&command)
+                .stdin(Stdio::null())
+                .output()
+                .await
+                .with_context(|| format!("Couldn't run \"{}\"", command))?;
+            if !output.status.success() {
+                bail!(
+                    "Theme hook failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits the pixels into `count` boxes along their widest channel and averages each box.
+fn median_cut(pixels: Vec<[u8; 3]>, count: usize) -> Vec<Rgb<u8>> {
+    let mut boxes = vec![pixels];
+    while boxes.len() < count {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(index, pixels)| {
+                let (channel, range) = widest_channel(pixels);
+                (index, channel, range)
+            })
+            .max_by_key(|(_, _, range)| *range);
+        let (index, channel) = match widest {
+            Some((index, channel, range)) if range > 0 => (index, channel),
+            // Every box has a single color.
+            _ => break,
+        };
+        let mut lower = boxes.swap_remove(index);
+        lower.sort_unstable_by_key(|pixel| pixel[channel]);
+        let upper = lower.split_off(lower.len() / 2);
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+    boxes.iter().map(|pixels| average(pixels)).collect()
+}
+
+fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let min = pixels.iter().map(|pixel| pixel[channel]).min().unwrap_or(0);
+            let max = pixels.iter().map(|pixel| pixel[channel]).max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or((0, 0))
+}
+
+fn average(pixels: &[[u8; 3]]) -> Rgb<u8> {
+    let mut sum = [0_u64; 3];
+    for pixel in pixels {
+        for channel in 0..3 {
+            sum[channel] += pixel[channel] as u64;
+        }
+    }
+    let count = pixels.len().max(1) as u64;
+    Rgb(sum.map(|channel| (channel / count) as u8))
+}
+
+/// Relative luminance between 0 and 1.
+fn luminance(color: Rgb<u8>) -> f32 {
+    let [r, g, b] = color.0.map(|channel| channel as f32 / 255.0);
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// `a` moved towards `b` by `amount` between 0 and 1.
+fn mix(a: Rgb<u8>, b: Rgb<u8>, amount: f32) -> Rgb<u8> {
+    let mut mixed = a;
+    for channel in 0..3 {
+        mixed.0[channel] =
+            (a.0[channel] as f32 * (1.0 - amount) + b.0[channel] as f32 * amount).round() as u8;
+    }
+    mixed
+}
+
+fn hex(color: Rgb<u8>) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.0[0], color.0[1], color.0[2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::TempFolder;
+    use image::RgbImage;
+
+    const RED: Rgb<u8> = Rgb([255, 0, 0]);
+    const BLUE: Rgb<u8> = Rgb([0, 0, 255]);
+
+    #[test]
+    fn cuts_into_the_colors_of_the_image() {
+        let pixels = [[255, 0, 0]; 10]
+            .into_iter()
+            .chain([[0, 0, 255]; 30])
+            .collect();
+        let mut colors = median_cut(pixels, DOMINANT_COLORS);
+        // The larger color may be cut in more boxes, no other color comes up.
+        colors.sort_by_key(|color| color.0);
+        colors.dedup();
+        assert_eq!(colors, [BLUE, RED]);
+
+        // A gradient is cut into even boxes.
+        let pixels = (0..=255).map(|shade| [shade; 3]).collect();
+        let colors = median_cut(pixels, 4);
+        let mut shades = colors.iter().map(|color| color.0[0]).collect::<Vec<_>>();
+        shades.sort_unstable();
+        assert_eq!(shades, [31, 95, 159, 223]);
+    }
+
+    #[test]
+    fn extracts_palettes() {
+        let folder = TempFolder::new("theme");
+        let path = folder.join("pollen.png");
+        RgbImage::from_fn(SAMPLE_SIZE, SAMPLE_SIZE, |_, y| {
+            if y < SAMPLE_SIZE / 2 {
+                RED
+            } else {
+                BLUE
+            }
+        })
+        .save(&path)
+        .unwrap();
+
+        let palette = Palette::extract(&path).unwrap();
+        // Blue is the darker one.
+        assert_eq!(palette.background, Rgb([0, 0, 128]));
+        assert_eq!(palette.foreground, Rgb([255, 128, 128]));
+        assert_eq!(palette.colors[0], palette.background);
+        assert_eq!(palette.colors[15], palette.foreground);
+        assert!(palette.colors.contains(&RED));
+    }
+
+    #[test]
+    fn writes_pywal_colors() {
+        let palette = Palette {
+            background: BLUE,
+            foreground: RED,
+            colors: [BLUE; 16],
+        };
+        let json = palette
+            .to_pywal_json(Path::new("/tmp/wallpaper.jpg"))
+            .unwrap();
+        let colors: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(colors["wallpaper"], "/tmp/wallpaper.jpg");
+        assert_eq!(colors["special"]["background"], "#0000ff");
+        assert_eq!(colors["special"]["foreground"], "#ff0000");
+        assert_eq!(colors["special"]["cursor"], "#ff0000");
+        // Listed as `color0` to `color15`, in order.
+        let positions = (0..16)
+            .map(|index| json.find(&format!("\"color{}\"", index)).unwrap())
+            .collect::<Vec<_>>();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(colors["colors"].as_object().unwrap().len(), 16);
+    }
+}
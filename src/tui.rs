@@ -1,11 +1,7 @@
 use console::Term;
-use crossterm::style::{StyledContent, Stylize};
 
 use anyhow::Result;
 
-pub const BEE: &str = "\u{1f41d}";
-pub const BRUSH: &str = "\u{1f9f9}";
-
 pub struct Tui {
     stdout: Term,
 }
@@ -26,16 +22,10 @@ impl Tui {
         self.stdout.hide_cursor()?;
         Ok(())
     }
+}
 
-    fn write_line(&self, line: StyledContent<&str>) -> Result<()> {
-        self.stdout.write_line(&format!("{}", line))?;
-        Ok(())
-    }
-
-    pub fn app_folder_not_found(&self) -> Result<()> {
-        self.write_line(
-            format!("{} App folder \"~/.pollenwall\" was not found. \"pollenwall\" has created it for you.", BEE)[..].yellow(),
-        )?;
-        Ok(())
+impl Drop for Tui {
+    fn drop(&mut self) {
+        let _ = self.stdout.show_cursor();
     }
 }